@@ -0,0 +1,136 @@
+use futures::TryStreamExt;
+use std::{collections::HashMap, hash::Hash};
+
+use ircjournal::Database;
+
+/// Finds the copies among messages fed in order of timestamp, then id, left by ingesting the same
+/// log more than once before messages had a fingerprint.
+///
+/// A log ingested n times has every line of a given second n times, so the number of copies of a
+/// second is the lowest multiplicity of its distinct lines. Lines repeated within that second are
+/// kept as many times as they were logged. The earliest rows are kept.
+struct Copies<T, K> {
+    timestamp: Option<T>,
+    /// Ids of each distinct line of the second.
+    lines: HashMap<K, Vec<i32>>,
+    ids: Vec<i32>,
+}
+
+impl<T: PartialEq, K: Eq + Hash> Copies<T, K> {
+    fn new() -> Self {
+        Self {
+            timestamp: None,
+            lines: HashMap::new(),
+            ids: vec![],
+        }
+    }
+
+    fn push(&mut self, timestamp: T, line: K, id: i32) {
+        if self.timestamp.as_ref() != Some(&timestamp) {
+            self.end_second();
+            self.timestamp = Some(timestamp);
+        }
+        self.lines.entry(line).or_default().push(id);
+    }
+
+    fn end_second(&mut self) {
+        let copies = self.lines.values().map(Vec::len).min().unwrap_or(1);
+        for (_, ids) in self.lines.drain() {
+            self.ids
+                .extend_from_slice(&ids[ids.len().div_ceil(copies)..]);
+        }
+    }
+
+    /// Ids of the copies, in order.
+    fn finish(mut self) -> Vec<i32> {
+        self.end_second();
+        self.ids.sort_unstable();
+        self.ids
+    }
+}
+
+/// Ids of the rows of `channel` that are copies of others, see [`Copies`]. Lines are the same if
+/// their `fingerprint` is, but for their occurrence.
+pub async fn duplicate_ids(db: &Database, channel: &str) -> Vec<i32> {
+    // language=sql
+    let mut rows = sqlx::query!(
+        r#"
+        SELECT "timestamp", coalesce("nick", '') "nick!", coalesce("opcode", '') "opcode!",
+               coalesce("line", '') "line!", "id"
+        FROM "message"
+        WHERE "channel" = $1
+        ORDER BY "timestamp", "id"
+        "#,
+        channel
+    )
+    .fetch(db);
+    let mut copies = Copies::new();
+    while let Some(row) = rows.try_next().await.unwrap() {
+        copies.push(row.timestamp, (row.nick, row.opcode, row.line), row.id);
+    }
+    copies.finish()
+}
+
+pub async fn channels(db: &Database) -> Vec<String> {
+    // language=sql
+    sqlx::query!(r#"SELECT "channel" "channel!" FROM all_channels()"#)
+        .fetch_all(db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.channel)
+        .collect()
+}
+
+pub async fn delete_messages(db: &Database, ids: &[i32]) -> u64 {
+    // language=sql
+    sqlx::query!(r#"DELETE FROM "message" WHERE "id" = ANY($1)"#, ids)
+        .execute(db)
+        .await
+        .unwrap()
+        .rows_affected()
+}
+
+#[test]
+fn test_copies() {
+    let copies = |rows: &[(u8, &'static str, i32)]| {
+        let mut copies = Copies::new();
+        for &(timestamp, line, id) in rows {
+            copies.push(timestamp, line, id);
+        }
+        copies.finish()
+    };
+    let log = [(1, "a", 1), (1, "b", 2), (2, "a", 3)];
+    assert!(copies(&log).is_empty());
+    // Ingested 3 times.
+    assert_eq!(
+        copies(&[
+            (1, "a", 1),
+            (1, "b", 2),
+            (1, "a", 4),
+            (1, "b", 5),
+            (1, "a", 7),
+            (1, "b", 8),
+            (2, "a", 3),
+            (2, "a", 6),
+            (2, "a", 9),
+        ]),
+        vec![4, 5, 6, 7, 8, 9]
+    );
+    // "a" said twice in the same second, ingested once, then twice.
+    let repeated = [(1, "a", 1), (1, "a", 2), (1, "b", 3)];
+    assert!(copies(&repeated).is_empty());
+    assert_eq!(
+        copies(&[
+            (1, "a", 1),
+            (1, "a", 2),
+            (1, "b", 3),
+            (1, "a", 4),
+            (1, "a", 5),
+            (1, "b", 6),
+        ]),
+        vec![4, 5, 6]
+    );
+    // Only part of the second ingested again: "a" may as well have been said twice.
+    assert!(copies(&[(1, "a", 1), (1, "b", 2), (1, "a", 3), (2, "a", 4)]).is_empty());
+}
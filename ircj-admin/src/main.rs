@@ -0,0 +1,104 @@
+use figment::{providers::Format, Figment};
+use log::info;
+
+use ircjournal::Database;
+
+mod dedupe;
+
+const USAGE: &str = "Usage: ircj-admin <command>
+
+Commands:
+    dedupe [--dry-run]    Delete lines saved more than once. With --dry-run, only count them.
+    hash-password         Hash the password read from stdin, for the users of ircj-serve.";
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct Config {
+    db: String,
+    batch_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            db: "".to_owned(),
+            batch_size: 10_000,
+        }
+    }
+}
+
+async fn dedupe(db: &Database, config: &Config, dry_run: bool) {
+    let mut total = 0;
+    for channel in dedupe::channels(db).await {
+        let ids = dedupe::duplicate_ids(db, &channel).await;
+        if ids.is_empty() {
+            continue;
+        }
+        total += ids.len();
+        if dry_run {
+            println!("{}: {} duplicates", channel, ids.len());
+            continue;
+        }
+        let mut deleted = 0;
+        for batch in ids.chunks(config.batch_size) {
+            deleted += dedupe::delete_messages(db, batch).await;
+            info!("{}: deleted {}/{}", channel, deleted, ids.len());
+        }
+        ircjournal::db::recount_stats(db, &channel).await;
+        println!("{}: deleted {} duplicates", channel, deleted);
+    }
+    println!(
+        "{} duplicates {}",
+        total,
+        if dry_run { "found" } else { "deleted" }
+    );
+}
+
+fn hash_password() {
+    use argon2::{
+        password_hash::{rand_core::OsRng, SaltString},
+        PasswordHasher,
+    };
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .expect("reading the password");
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2::Argon2::default()
+        .hash_password(password.trim_end_matches(['\r', '\n']).as_bytes(), &salt)
+        .expect("hashing the password");
+    println!("{}", hash);
+}
+
+#[tokio::main]
+#[allow(clippy::result_large_err)]
+async fn main() -> Result<(), figment::Error> {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let dry_run = match args.as_slice() {
+        ["dedupe"] => false,
+        ["dedupe", "--dry-run"] => true,
+        ["hash-password"] => {
+            hash_password();
+            return Ok(());
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config: Config = Figment::new()
+        .merge(figment::providers::Serialized::defaults(Config::default()))
+        .merge(figment::providers::Toml::file("ircj-admin.toml"))
+        .merge(figment::providers::Env::prefixed("IRCJ_"))
+        .extract()?;
+
+    let pool = ircjournal::db::create_db(&config.db)
+        .await
+        .unwrap_or_else(|_| panic!("Connecting and migrating the database at {}", &config.db));
+
+    dedupe(&pool, &config, dry_run).await;
+    Ok(())
+}
@@ -0,0 +1,272 @@
+use log::{debug, info};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use ircjournal::{line_to_new_message, model::ServerChannel, NewMessage, Occurrences};
+
+pub mod proto;
+pub mod session;
+
+use proto::Message;
+use session::Session;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Config {
+    pub db: String,
+    /// Name of the network, as the server part of channel names. Use the same name as the log
+    /// files of other clients to merge logs.
+    pub server: String,
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub nick: String,
+    pub password: Option<String>,
+    pub channels: Vec<String>,
+    pub reconnect_delay_secs: u64,
+    /// The server is pinged after this long without hearing from it.
+    pub ping_after_secs: u64,
+    /// The connection is deemed dead when the server does not answer a ping in time.
+    pub ping_timeout_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            db: "".to_owned(),
+            server: "".to_owned(),
+            host: "".to_owned(),
+            port: 6697,
+            tls: true,
+            nick: "ircjournal".to_owned(),
+            password: None,
+            channels: vec![],
+            reconnect_delay_secs: 30,
+            ping_after_secs: 180,
+            ping_timeout_secs: 60,
+        }
+    }
+}
+
+async fn send<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> std::io::Result<()> {
+    debug!("> {}", line);
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    writer.flush().await
+}
+
+/// Registers on `stream`, joins the configured channels and sends the lines to log to `tx`,
+/// until the connection is closed, or stops answering pings.
+pub async fn run<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    config: &Config,
+    tx: tokio::sync::mpsc::Sender<NewMessage>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut session = Session::new(&config.nick, &config.channels);
+    let mut occurrences = Occurrences::default();
+
+    if let Some(password) = &config.password {
+        send(&mut writer, &format!("PASS {}", password)).await?;
+    }
+    send(&mut writer, &format!("NICK {}", session.nick)).await?;
+    send(
+        &mut writer,
+        &format!("USER {} 0 * :ircjournal", session.nick),
+    )
+    .await?;
+
+    let mut buf = Vec::new();
+    let mut pinged = false;
+    loop {
+        // A link that died silently never closes: ping the server when it is quiet for long.
+        let silence = Duration::from_secs(if pinged {
+            config.ping_timeout_secs
+        } else {
+            config.ping_after_secs
+        });
+        // On timeout, what was read of the line stays in `buf` and the next read completes it.
+        let read = match tokio::time::timeout(silence, reader.read_until(b'\n', &mut buf)).await {
+            Ok(read) => read?,
+            Err(_) if pinged => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "no answer to PING",
+                ))
+            }
+            Err(_) => {
+                send(&mut writer, "PING :ircjournal").await?;
+                pinged = true;
+                continue;
+            }
+        };
+        if read == 0 {
+            return Ok(());
+        }
+        pinged = false;
+        // Not everyone speaks UTF-8.
+        let line = String::from_utf8_lossy(&buf).into_owned();
+        buf.clear();
+        let msg = match Message::parse(&line) {
+            Some(msg) => msg,
+            None => continue,
+        };
+        match msg.command.as_str() {
+            "PING" => {
+                send(
+                    &mut writer,
+                    &format!("PONG :{}", msg.param(0).unwrap_or_default()),
+                )
+                .await?
+            }
+            // RPL_WELCOME
+            "001" => {
+                info!("Connected to {}", &config.server);
+                for channel in &config.channels {
+                    send(&mut writer, &format!("JOIN {}", channel)).await?;
+                }
+            }
+            // ERR_NICKNAMEINUSE
+            "433" => {
+                session.nick.push('_');
+                send(&mut writer, &format!("NICK {}", session.nick)).await?;
+            }
+            _ => {}
+        }
+        let timestamp = chrono::Utc::now();
+        for (channel, line) in session.on_message(&msg) {
+            let sc = ServerChannel {
+                server: config.server.clone(),
+                channel,
+            };
+            if let Some(message) = line_to_new_message(line, &sc, timestamp) {
+                tx.send(occurrences.number(message))
+                    .await
+                    .expect("channel closed");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::{run, Config};
+
+    /// Plays a scripted ircd: waits for each expected client line, then sends the replies.
+    async fn fake_ircd(listener: TcpListener, script: &[(&str, &str)]) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        for (expected, replies) in script {
+            loop {
+                let line = lines.next_line().await.unwrap().unwrap();
+                if line.starts_with(expected) {
+                    break;
+                }
+            }
+            writer.write_all(replies.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ircd = tokio::spawn(async move {
+            fake_ircd(
+                listener,
+                &[
+                    (
+                        "NICK bot",
+                        ":irc.test 433 * bot :Nickname is already in use\r\n",
+                    ),
+                    ("NICK bot_", ":irc.test 001 bot_ :Welcome\r\n"),
+                    (
+                        "JOIN #chan",
+                        ":bot_!u@h JOIN #chan\r\n\
+                         :irc.test 353 bot_ = #chan :bot_ @zopieux\r\n\
+                         PING :irc.test\r\n",
+                    ),
+                    (
+                        "PONG :irc.test",
+                        ":zopieux!u@h PRIVMSG #chan :hello\r\n\
+                         :zopieux!u@h QUIT :Bye\r\n",
+                    ),
+                ],
+            )
+            .await
+        });
+
+        let config = Config {
+            server: "test".to_string(),
+            nick: "bot".to_string(),
+            channels: vec!["#chan".to_string()],
+            ..Config::default()
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let stream = TcpStream::connect(addr).await.unwrap();
+        run(stream, &config, tx).await.unwrap();
+        ircd.await.unwrap();
+
+        let mut messages = vec![];
+        while let Some(m) = rx.recv().await {
+            messages.push((m.channel.unwrap(), m.opcode, m.nick.unwrap(), m.line));
+        }
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "test/#chan".to_string(),
+                    Some("joined".to_string()),
+                    "bot_".to_string(),
+                    None
+                ),
+                (
+                    "test/#chan".to_string(),
+                    None,
+                    "zopieux".to_string(),
+                    Some("hello".to_string())
+                ),
+                (
+                    "test/#chan".to_string(),
+                    Some("quit".to_string()),
+                    "zopieux".to_string(),
+                    None
+                ),
+            ]
+        );
+
+        // The server goes quiet, and does not answer the ping either.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ircd = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let mut received = vec![];
+            // Until the bot hangs up.
+            while let Ok(Some(line)) = lines.next_line().await {
+                received.push(line);
+            }
+            received
+        });
+        let config = Config {
+            ping_after_secs: 1,
+            ping_timeout_secs: 1,
+            ..config
+        };
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let error = run(stream, &config, tx).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert_eq!(
+            ircd.await.unwrap(),
+            vec!["NICK bot", "USER bot 0 * :ircjournal", "PING :ircjournal"]
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use figment::{providers::Format, Figment};
+use log::{debug, warn};
+use tokio::net::TcpStream;
+
+use ircj_bot::{run, Config};
+use ircjournal::{
+    model::{NewMessage, ServerChannel},
+    Database,
+};
+
+/// Saves messages as they come, batching the ones that arrive together.
+async fn inserter_task(db: Database, mut message_queue: tokio::sync::mpsc::Receiver<NewMessage>) {
+    while let Some(message) = message_queue.recv().await {
+        let mut batch = vec![message];
+        while let Ok(message) = message_queue.try_recv() {
+            batch.push(message);
+        }
+        let inserted = ircjournal::db::batch_insert_messages_and_notify(&db, &batch).await;
+        debug!("Inserted {}", inserted.unwrap_or_default());
+    }
+}
+
+async fn connect_and_run(
+    config: &Config,
+    tx: tokio::sync::mpsc::Sender<NewMessage>,
+) -> std::io::Result<()> {
+    let stream = TcpStream::connect((config.host.as_str(), config.port)).await?;
+    if !config.tls {
+        return run(stream, config, tx).await;
+    }
+    let connector =
+        tokio_native_tls::native_tls::TlsConnector::new().map_err(std::io::Error::other)?;
+    let stream = tokio_native_tls::TlsConnector::from(connector)
+        .connect(&config.host, stream)
+        .await
+        .map_err(std::io::Error::other)?;
+    run(stream, config, tx).await
+}
+
+#[tokio::main]
+#[allow(clippy::result_large_err)]
+async fn main() -> Result<(), figment::Error> {
+    env_logger::init();
+
+    let config: Config = Figment::new()
+        .merge(figment::providers::Serialized::defaults(Config::default()))
+        .merge(figment::providers::Toml::file("ircj-bot.toml"))
+        .merge(figment::providers::Env::prefixed("IRCJ_"))
+        .extract()?;
+
+    let pool = ircjournal::db::create_db(&config.db)
+        .await
+        .unwrap_or_else(|_| panic!("Connecting and migrating the database at {}", &config.db));
+
+    for channel in &config.channels {
+        let sc = ServerChannel::new(&config.server, channel);
+        ircjournal::db::save_channel(&pool, &sc, None).await;
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<NewMessage>(128);
+    tokio::spawn(async move { inserter_task(pool, rx).await });
+
+    loop {
+        match connect_and_run(&config, tx.clone()).await {
+            Ok(()) => warn!("Disconnected from {}", &config.host),
+            Err(err) => warn!("Connection to {} failed: {}", &config.host, err),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(config.reconnect_delay_secs)).await;
+    }
+}
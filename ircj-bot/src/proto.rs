@@ -0,0 +1,79 @@
+/// A message of the IRC client protocol, see RFC 1459 § 2.3.1. Tags are ignored.
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl Message {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ')?.1.trim_start();
+        }
+        let prefix = match rest.strip_prefix(':') {
+            Some(stripped) => {
+                let (prefix, after) = stripped.split_once(' ')?;
+                rest = after.trim_start();
+                Some(prefix.to_string())
+            }
+            None => None,
+        };
+        let (middle, trailing) = match rest.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut words = middle.split(' ').filter(|w| !w.is_empty());
+        let command = words.next()?.to_ascii_uppercase();
+        let params = words
+            .map(str::to_string)
+            .chain(trailing.map(str::to_string))
+            .collect();
+        Some(Self {
+            prefix,
+            command,
+            params,
+        })
+    }
+
+    /// The nick of the sender, if the message comes from a user.
+    pub fn nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        match prefix.split_once('!') {
+            Some((nick, _)) => Some(nick),
+            // Servers have dots in their names, nicks cannot.
+            None if !prefix.contains('.') => Some(prefix),
+            None => None,
+        }
+    }
+
+    pub fn param(&self, n: usize) -> Option<&str> {
+        self.params.get(n).map(String::as_str)
+    }
+}
+
+#[test]
+fn test_parse_message() {
+    assert_eq!(Message::parse(""), None);
+    assert_eq!(
+        Message::parse("PING :irc.example.net\r\n"),
+        Some(Message {
+            prefix: None,
+            command: "PING".to_string(),
+            params: vec!["irc.example.net".to_string()],
+        })
+    );
+    let m = Message::parse(":zopieux!~zopieux@host PRIVMSG #chan :hello :) there").unwrap();
+    assert_eq!(m.nick(), Some("zopieux"));
+    assert_eq!(m.command, "PRIVMSG");
+    assert_eq!(m.params, vec!["#chan", "hello :) there"]);
+    let m = Message::parse("@time=2021-01-01T00:00:00Z :irc.example.net 001 bot :Welcome").unwrap();
+    assert_eq!(m.nick(), None);
+    assert_eq!(m.command, "001");
+    assert_eq!(m.params, vec!["bot", "Welcome"]);
+    let m = Message::parse(":haileda JOIN #chan").unwrap();
+    assert_eq!(m.nick(), Some("haileda"));
+    assert_eq!(m.param(0), Some("#chan"));
+    assert_eq!(m.param(1), None);
+}
@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+
+use ircjournal::IrcLine;
+
+use crate::proto::Message;
+
+#[derive(Default)]
+struct Channel {
+    /// Name the lines are logged under.
+    name: String,
+    members: HashSet<String>,
+    topic: String,
+}
+
+/// What the bot knows of the channels it is in, to turn server messages into [`IrcLine`]s.
+/// QUIT and NICK are not sent to a channel: they are logged in every channel the user was in,
+/// like clients do.
+pub struct Session {
+    pub nick: String,
+    /// Configured channel names, by [`irc_lowercase`] name.
+    names: HashMap<String, String>,
+    /// By [`irc_lowercase`] name.
+    channels: HashMap<String, Channel>,
+}
+
+const ACTION: &str = "\x01ACTION ";
+
+fn strip_nick_mode(nick: &str) -> &str {
+    nick.trim_start_matches(['~', '&', '@', '%', '+'])
+}
+
+/// Channel names are case insensitive, and `[]\~` are the uppercase of `{}|^` (RFC 1459).
+pub fn irc_lowercase(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '[' => '{',
+            ']' => '}',
+            '\\' => '|',
+            '~' => '^',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+impl Session {
+    /// Lines of `channels` are logged under these names, whatever the casing the server uses.
+    pub fn new(nick: &str, channels: &[String]) -> Self {
+        Self {
+            nick: nick.to_string(),
+            names: channels
+                .iter()
+                .map(|name| (irc_lowercase(name), name.clone()))
+                .collect(),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Returns the lines to log for `msg`, along with their channel.
+    pub fn on_message(&mut self, msg: &Message) -> Vec<(String, IrcLine)> {
+        let nick = msg.nick().unwrap_or_default().to_string();
+        let param = |n| msg.param(n).unwrap_or_default().to_string();
+        let key = irc_lowercase(&param(0));
+        let (in_target, target) = match self.channels.get(&key) {
+            Some(channel) => (true, channel.name.clone()),
+            None => (false, param(0)),
+        };
+        match msg.command.as_str() {
+            "PRIVMSG" if in_target => {
+                let text = param(1);
+                let line = if let Some(action) = text.strip_prefix(ACTION) {
+                    IrcLine::Me {
+                        nick,
+                        line: action.trim_end_matches('\x01').to_string(),
+                    }
+                } else if let Some(command) = text.strip_prefix('\x01') {
+                    IrcLine::Ctcp {
+                        nick,
+                        command: command.trim_end_matches('\x01').to_string(),
+                    }
+                } else {
+                    IrcLine::Message { nick, line: text }
+                };
+                vec![(target, line)]
+            }
+            // CTCP replies are not for humans.
+            "NOTICE" if in_target && !param(1).starts_with('\x01') => {
+                vec![(
+                    target,
+                    IrcLine::Notice {
+                        nick,
+                        line: param(1),
+                    },
+                )]
+            }
+            "MODE" if in_target => {
+                let mode = msg.params[1..].join(" ");
+                vec![(target, IrcLine::ModeChanged { nick, mode })]
+            }
+            "JOIN" => {
+                if nick == self.nick {
+                    let name = self.names.get(&key).cloned().unwrap_or(target);
+                    let channel = Channel {
+                        name,
+                        ..Channel::default()
+                    };
+                    self.channels.insert(key.clone(), channel);
+                }
+                match self.channels.get_mut(&key) {
+                    Some(channel) => {
+                        channel.members.insert(nick.clone());
+                        vec![(channel.name.clone(), IrcLine::Joined { nick })]
+                    }
+                    None => vec![],
+                }
+            }
+            "PART" if in_target => {
+                if nick == self.nick {
+                    self.channels.remove(&key);
+                } else if let Some(channel) = self.channels.get_mut(&key) {
+                    channel.members.remove(&nick);
+                }
+                vec![(
+                    target,
+                    IrcLine::Left {
+                        nick,
+                        reason: param(1),
+                    },
+                )]
+            }
+            "KICK" if in_target => {
+                let kicked = param(1);
+                if kicked == self.nick {
+                    self.channels.remove(&key);
+                } else if let Some(channel) = self.channels.get_mut(&key) {
+                    channel.members.remove(&kicked);
+                }
+                vec![(
+                    target,
+                    IrcLine::Kicked {
+                        oper_nick: nick,
+                        nick: kicked,
+                        reason: param(2),
+                    },
+                )]
+            }
+            "TOPIC" if in_target => {
+                let channel = self.channels.get_mut(&key).unwrap();
+                let old = std::mem::replace(&mut channel.topic, param(1));
+                vec![(
+                    target,
+                    IrcLine::TopicChanged {
+                        nick,
+                        old,
+                        new: param(1),
+                    },
+                )]
+            }
+            "QUIT" => self
+                .channels
+                .values_mut()
+                .filter_map(|channel| {
+                    channel.members.remove(&nick).then(|| {
+                        (
+                            channel.name.clone(),
+                            IrcLine::Quit {
+                                nick: nick.clone(),
+                                reason: param(0),
+                            },
+                        )
+                    })
+                })
+                .collect(),
+            "NICK" => {
+                let new = param(0);
+                if nick == self.nick {
+                    self.nick = new.clone();
+                }
+                self.channels
+                    .values_mut()
+                    .filter_map(|channel| {
+                        channel.members.remove(&nick).then(|| {
+                            channel.members.insert(new.clone());
+                            (
+                                channel.name.clone(),
+                                IrcLine::NickChanged {
+                                    old: nick.clone(),
+                                    new: new.clone(),
+                                },
+                            )
+                        })
+                    })
+                    .collect()
+            }
+            // RPL_TOPIC: <me> <channel> :<topic>
+            "332" => {
+                if let Some(channel) = self.channels.get_mut(&irc_lowercase(&param(1))) {
+                    channel.topic = param(2);
+                }
+                vec![]
+            }
+            // RPL_NAMREPLY: <me> <symbol> <channel> :<nicks>
+            "353" => {
+                if let Some(channel) = self.channels.get_mut(&irc_lowercase(&param(2))) {
+                    channel.members.extend(
+                        param(3)
+                            .split(' ')
+                            .filter(|n| !n.is_empty())
+                            .map(|n| strip_nick_mode(n).to_string()),
+                    );
+                }
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[test]
+fn test_session() {
+    let mut session = Session::new("bot", &["#chan".to_string()]);
+    let mut on = |line: &str| session.on_message(&Message::parse(line).unwrap());
+    let chan = |line| vec![("#chan".to_string(), line)];
+    let s = |s: &str| s.to_string();
+
+    // Not in the channel yet.
+    assert_eq!(on(":zopieux!u@h PRIVMSG #chan :hello"), vec![]);
+    assert_eq!(
+        on(":bot!u@h JOIN #chan"),
+        chan(IrcLine::Joined { nick: s("bot") })
+    );
+    assert_eq!(on(":irc.example.net 332 bot #chan :Old topic"), vec![]);
+    assert_eq!(
+        on(":irc.example.net 353 bot = #chan :bot @zopieux +haileda"),
+        vec![]
+    );
+    assert_eq!(
+        on(":zopieux!u@h PRIVMSG #chan :hello"),
+        chan(IrcLine::Message {
+            nick: s("zopieux"),
+            line: s("hello"),
+        })
+    );
+    assert_eq!(on(":zopieux!u@h PRIVMSG bot :private"), vec![]);
+    assert_eq!(
+        on(":haileda!u@h PRIVMSG #chan :\x01ACTION waves\x01"),
+        chan(IrcLine::Me {
+            nick: s("haileda"),
+            line: s("waves"),
+        })
+    );
+    assert_eq!(
+        on(":haileda!u@h PRIVMSG #chan :\x01VERSION\x01"),
+        chan(IrcLine::Ctcp {
+            nick: s("haileda"),
+            command: s("VERSION"),
+        })
+    );
+    assert_eq!(
+        on(":ChanServ!u@h NOTICE #chan :Welcome"),
+        chan(IrcLine::Notice {
+            nick: s("ChanServ"),
+            line: s("Welcome"),
+        })
+    );
+    assert_eq!(on(":haileda!u@h NOTICE #chan :\x01VERSION 1.0\x01"), vec![]);
+    assert_eq!(
+        on(":ChanServ!u@h MODE #chan +o zopieux"),
+        chan(IrcLine::ModeChanged {
+            nick: s("ChanServ"),
+            mode: s("+o zopieux"),
+        })
+    );
+    assert_eq!(
+        on(":zopieux!u@h TOPIC #chan :New topic"),
+        chan(IrcLine::TopicChanged {
+            nick: s("zopieux"),
+            old: s("Old topic"),
+            new: s("New topic"),
+        })
+    );
+    assert_eq!(
+        on(":Tycale!u@h JOIN #chan"),
+        chan(IrcLine::Joined { nick: s("Tycale") })
+    );
+    assert_eq!(
+        on(":Tycale!u@h PART #chan :Cya"),
+        chan(IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s("Cya"),
+        })
+    );
+    assert_eq!(
+        on(":zopieux!u@h NICK :zop"),
+        chan(IrcLine::NickChanged {
+            old: s("zopieux"),
+            new: s("zop"),
+        })
+    );
+    assert_eq!(
+        on(":zop!u@h KICK #chan haileda :no u"),
+        chan(IrcLine::Kicked {
+            oper_nick: s("zop"),
+            nick: s("haileda"),
+            reason: s("no u"),
+        })
+    );
+    // haileda is gone, only zop quitting is logged.
+    assert_eq!(on(":haileda!u@h QUIT :Bye"), vec![]);
+    assert_eq!(
+        on(":zop!u@h QUIT :Quit: WeeChat 2.2"),
+        chan(IrcLine::Quit {
+            nick: s("zop"),
+            reason: s("Quit: WeeChat 2.2"),
+        })
+    );
+    assert_eq!(
+        on(":bot!u@h PART #chan"),
+        chan(IrcLine::Left {
+            nick: s("bot"),
+            reason: s(""),
+        })
+    );
+    assert_eq!(on(":zopieux!u@h PRIVMSG #chan :hello"), vec![]);
+}
+
+#[test]
+fn test_session_case() {
+    assert_eq!(irc_lowercase("#Chan[ops]\\~"), "#chan{ops}|^");
+    let mut session = Session::new("bot", &["#Chan[ops]".to_string()]);
+    let mut on = |line: &str| session.on_message(&Message::parse(line).unwrap());
+    let chan = |line| vec![("#Chan[ops]".to_string(), line)];
+    let s = |s: &str| s.to_string();
+
+    // Logged under the configured name, whatever the casing.
+    assert_eq!(
+        on(":bot!u@h JOIN #chan{ops}"),
+        chan(IrcLine::Joined { nick: s("bot") })
+    );
+    assert_eq!(
+        on(":irc.example.net 353 bot = #CHAN[OPS] :bot zopieux"),
+        vec![]
+    );
+    assert_eq!(
+        on(":zopieux!u@h PRIVMSG #CHAN[ops] :hello"),
+        chan(IrcLine::Message {
+            nick: s("zopieux"),
+            line: s("hello"),
+        })
+    );
+    assert_eq!(
+        on(":zopieux!u@h QUIT :Bye"),
+        chan(IrcLine::Quit {
+            nick: s("zopieux"),
+            reason: s("Bye"),
+        })
+    );
+    assert_eq!(
+        on(":bot!u@h PART #chan{OPS}"),
+        chan(IrcLine::Left {
+            nick: s("bot"),
+            reason: s(""),
+        })
+    );
+    assert_eq!(on(":zopieux!u@h PRIVMSG #chan{ops} :hello"), vec![]);
+}
@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+# Times searches in the channel of search.sql, against a running ircj-serve:
+#   ircj-serve/bench/search.sh [http://localhost:8000]
+set -euo pipefail
+
+url="${1:-http://localhost:8000}/api/v1/bench:~h~big/search"
+# A cursor deep into the channel: its oldest result.
+oldest=$(psql "$DATABASE_URL" -Atc "SELECT min(id) FROM message WHERE channel = 'bench/#big'")
+
+search() {
+  local name=$1
+  shift
+  curl -sS -o /dev/null -G "$url" -w "%{http_code} %{time_total}s  $name\n" "$@"
+}
+
+search "common word, first page" --data-urlencode "query=build"
+search "common word, newer than the oldest" --data-urlencode "query=build" --data-urlencode "after=$oldest"
+search "common word, filtered" --data-urlencode "query=build nick:nick7 on:2020-01-02"
+search "rare word, first page" --data-urlencode "query=flaky"
+search "phrase and exclusion" --data-urlencode 'query="build failed" -flaky'
+search "substring" --data-urlencode "query=c4ca4238a0b9" --data-urlencode "mode=substring"
+search "regular expression" --data-urlencode "query=^the build (passed|failed) because" --data-urlencode "mode=regex"
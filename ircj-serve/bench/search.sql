@@ -0,0 +1,23 @@
+-- A large channel to benchmark search with, in a scratch UTF-8 database migrated by ircj-serve:
+--   psql "$DATABASE_URL" -v lines=10000000 -f ircj-serve/bench/search.sql
+-- Every line has the common word "build", one in a thousand the rare word "flaky", and each
+-- has a hash to find by substring.
+\if :{?lines}
+\else
+    \set lines 1000000
+\endif
+
+DELETE FROM "message" WHERE "channel" = 'bench/#big';
+INSERT INTO "channel" ("name", "private") VALUES ('bench/#big', FALSE) ON CONFLICT DO NOTHING;
+
+INSERT INTO "message" ("channel", "nick", "line", "timestamp")
+SELECT 'bench/#big',
+       'nick' || (i % 50),
+       'the build ' || (ARRAY ['passed', 'failed', 'is running', 'was retried'])[1 + i % 4] ||
+       CASE WHEN i % 1000 = 0 THEN ' because of a flaky test' ELSE '' END ||
+       ' at ' || left(md5(i::text), 12),
+       '2020-01-01'::timestamptz + i * interval '10 seconds'
+FROM generate_series(1, :lines) i;
+
+ANALYZE "message";
+ANALYZE "message_search";
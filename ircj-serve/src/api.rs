@@ -0,0 +1,198 @@
+use rocket::{response::status::BadRequest, serde::json::Json, Route, State};
+use serde::Serialize;
+
+use ircjournal::{
+    model::{Message, ServerChannel},
+    Database,
+};
+
+use crate::{
+    db::{Cursor, SEARCH_COUNT_LIMIT},
+    query::{SearchMode, SearchQuery},
+    ChannelInfo, Day, DisplayTz, Viewer,
+};
+
+#[derive(Serialize)]
+struct DayMessages {
+    day: Day,
+    /// Pass as `before` for earlier messages of the day, if any.
+    older: Option<i32>,
+    /// Pass as `after` for later messages of the day, if any.
+    newer: Option<i32>,
+    /// In chronological order, up to 1,000.
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize)]
+struct MonthIndex {
+    year: i32,
+    month: u32,
+    /// Days of the month when people talked, in order.
+    days: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct SearchPage {
+    query: String,
+    /// Counted up to 10,000.
+    total: i64,
+    /// Whether there are more than `total` results.
+    more_than_total: bool,
+    /// Pass as `before` for older results, if any.
+    older: Option<i32>,
+    /// Pass as `after` for newer results, if any.
+    newer: Option<i32>,
+    /// Most recent first. The line of each message is an excerpt around the matches.
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+    error: String,
+}
+
+#[get("/channels")]
+async fn channels(db: &State<Database>, viewer: Viewer) -> Json<Vec<ServerChannel>> {
+    Json(crate::db::channels(db, &viewer).await)
+}
+
+#[get("/<sc>")]
+async fn channel_info(
+    db: &State<Database>,
+    sc: ServerChannel,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Json<ChannelInfo>> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let today = Day::today(&tz.0);
+    crate::db::channel_info(db, &sc, &today, &tz.0)
+        .await
+        .map(Json)
+}
+
+#[get("/<sc>/<day>?<after>&<before>")]
+async fn channel_day(
+    db: &State<Database>,
+    sc: ServerChannel,
+    day: Day,
+    after: Option<i32>,
+    before: Option<i32>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Json<DayMessages>> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let (from, to) = (day.midnight(&tz.0), day.succ().midnight(&tz.0));
+    let cursor = Cursor::new(after, before);
+    let page = crate::db::messages_channel_range(db, &sc, &from, Some(&to), cursor).await;
+    let older = page
+        .records
+        .first()
+        .filter(|_| page.has_earlier)
+        .map(|m| m.id);
+    let newer = page.records.last().filter(|_| page.has_later).map(|m| m.id);
+    Some(Json(DayMessages {
+        day,
+        older,
+        newer,
+        messages: page.records,
+    }))
+}
+
+#[get("/<sc>/month/<year>/<month>")]
+async fn channel_month(
+    db: &State<Database>,
+    sc: ServerChannel,
+    year: i32,
+    month: u32,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Json<MonthIndex>> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    // Rejects invalid months.
+    chrono::NaiveDate::from_ymd_opt(year, month, 1)?;
+    let mut days: Vec<u32> = crate::db::channel_month_index(db, &sc, year, month, &tz.0)
+        .await
+        .into_iter()
+        .collect();
+    days.sort_unstable();
+    Some(Json(MonthIndex { year, month, days }))
+}
+
+#[get("/<sc>/search?<query>&<mode>&<after>&<before>")]
+#[allow(clippy::too_many_arguments)]
+async fn channel_search(
+    db: &State<Database>,
+    sc: ServerChannel,
+    query: &str,
+    mode: Option<SearchMode>,
+    after: Option<i32>,
+    before: Option<i32>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Result<Json<SearchPage>, BadRequest<Json<ErrorMessage>>>> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let cursor = Cursor::new(after, before);
+    let results = match SearchQuery::parse(query, mode.unwrap_or_default()) {
+        Ok(parsed) => crate::db::search(db, &[sc.to_string()], &parsed, cursor, &tz.0).await,
+        Err(error) => Err(error),
+    };
+    let results = match results {
+        Ok(results) => results,
+        Err(error) => {
+            return Some(Err(BadRequest(Json(ErrorMessage {
+                error: error.to_string(),
+            }))))
+        }
+    };
+    let (total, more_than_total) = (
+        results.total.min(SEARCH_COUNT_LIMIT),
+        results.total_capped(),
+    );
+    let page = results.page;
+    let older = page
+        .records
+        .last()
+        .filter(|_| page.has_earlier)
+        .map(|m| m.id);
+    let newer = page
+        .records
+        .first()
+        .filter(|_| page.has_later)
+        .map(|m| m.id);
+    let messages = page
+        .records
+        .into_iter()
+        .map(|message| Message {
+            line: message.line.as_deref().map(crate::view::clean),
+            payload: message.payload.as_deref().map(crate::view::clean),
+            ..message
+        })
+        .collect();
+    Some(Ok(Json(SearchPage {
+        query: query.to_string(),
+        total,
+        more_than_total,
+        older,
+        newer,
+        messages,
+    })))
+}
+
+/// Read-only JSON API, mounted under `/api/v1`. Guarded like the HTML routes.
+pub fn routes() -> Vec<Route> {
+    routes![
+        channels,
+        channel_info,
+        channel_month,
+        channel_search,
+        channel_day,
+    ]
+}
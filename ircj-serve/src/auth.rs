@@ -0,0 +1,100 @@
+use argon2::{
+    password_hash::rand_core::{OsRng, RngCore},
+    Argon2, PasswordHash, PasswordVerifier,
+};
+use rocket::{config::SecretKey, fairing::AdHoc};
+
+use crate::{Config, Viewer};
+
+/// Name of the private cookie holding the logged in user.
+pub(crate) const USER_COOKIE: &str = "user";
+
+/// Whether users can log in, which needs rocket's `secret_key` to encrypt their cookie.
+pub(crate) struct CanLogIn(pub(crate) bool);
+
+/// Mounts the login routes if a `secret_key` is configured. Otherwise, nobody can log in, and a
+/// throwaway key lets rocket start anyway, as it refuses to outside of debug.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_ignite("Enable logging in", |rocket| async move {
+        let key = rocket.figment().extract_inner::<SecretKey>("secret_key");
+        if key.is_ok_and(|key| key.is_provided()) {
+            return rocket
+                .manage(CanLogIn(true))
+                .mount("/", crate::route::login_routes());
+        }
+        let config = rocket.state::<Config>().unwrap(); // attached before.
+        if !config.users.is_empty() {
+            warn!("No `secret_key` is configured, so `users` cannot log in.");
+        }
+        let mut key = [0; 64];
+        OsRng.fill_bytes(&mut key);
+        let figment = rocket.figment().clone().merge(("secret_key", key.to_vec()));
+        rocket.manage(CanLogIn(false)).configure(figment)
+    })
+}
+
+impl Viewer {
+    pub(crate) fn new(config: &Config, user: Option<String>) -> Self {
+        let denied = config
+            .acl
+            .iter()
+            .filter(|(_, users)| !user.as_ref().is_some_and(|user| users.contains(user)))
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        Self { user, denied }
+    }
+}
+
+/// Hash of a throwaway password, checked for unknown users so that they take as long as known
+/// ones, which could otherwise be told apart by timing. Same parameters as `ircj-admin
+/// hash-password`.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$Fc+IvXVfOYBSgik4mJj4+w$YvH7mVAb1cZ7usgQ0eZVdhCUk6GEprOFb7ndYw3D/bY";
+
+/// Whether `password` is the one of `user`. Slow on purpose, even when `user` is unknown.
+pub(crate) async fn check_password(config: &Config, user: &str, password: &str) -> bool {
+    let (hash, known) = match config.users.get(user) {
+        Some(hash) => (hash.clone(), true),
+        None => (DUMMY_HASH.to_string(), false),
+    };
+    let password = password.to_owned();
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await
+    .unwrap_or(false)
+        && known
+}
+
+#[test]
+fn test_viewer() {
+    let config = Config {
+        acl: [("libera/#staff".to_string(), vec!["alice".to_string()])].into(),
+        ..Config::default()
+    };
+    assert_eq!(Viewer::new(&config, None).denied, vec!["libera/#staff"]);
+    assert_eq!(
+        Viewer::new(&config, Some("bob".to_string())).denied,
+        vec!["libera/#staff"]
+    );
+    assert!(Viewer::new(&config, Some("alice".to_string()))
+        .denied
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_check_password() {
+    let config = Config {
+        users: [("alice".to_string(), DUMMY_HASH.to_string())].into(),
+        ..Config::default()
+    };
+    assert!(check_password(&config, "alice", "not a password").await);
+    assert!(!check_password(&config, "alice", "hunter2").await);
+    assert!(!check_password(&config, "bob", "not a password").await);
+}
@@ -1,40 +1,161 @@
-use lazy_static::lazy_static;
 use std::{collections::HashSet, str::FromStr};
 
-use crate::{ChannelInfo, Day};
+use chrono_tz::Tz;
+use rocket::futures::stream::BoxStream;
+
+use crate::{
+    query::{QueryError, SearchQuery},
+    ChannelInfo, Day, Viewer,
+};
 use ircjournal::{
-    model::{Message, ServerChannel},
+    model::{Datetime, Message, ServerChannel},
     Database,
 };
 
-pub(crate) type MessagesPerDay = (Day, Vec<Message>);
+pub(crate) type LinesPerDay<'a> = (Day, Vec<SearchLine<'a>>);
 
 const SEARCH_PAGE_SIZE: u64 = 100;
+/// Counting every result is slow for common words in large channels.
+pub(crate) const SEARCH_COUNT_LIMIT: i64 = 10_000;
+/// Most lines shown before and after each search result.
+pub(crate) const SEARCH_CONTEXT_LIMIT: u8 = 10;
+const RANGE_PAGE_SIZE: usize = 1_000;
 const HARD_NICK_LIMIT: u64 = 1_000;
-pub(crate) const HARD_MESSAGE_LIMIT: usize = 10_000;
+const STATS_TOP_NICKS: i64 = 30;
+const STATS_BUSIEST_DAYS: i64 = 10;
+const STATS_RECENT_LINKS: i64 = 20;
+/// Months of activity shown for each nick, up to the last month of the channel.
+pub(crate) const STATS_NICK_MONTHS: usize = 12;
+/// PostgreSQL's invalid_regular_expression error.
+const INVALID_REGEX: &str = "2201B";
+/// PostgreSQL's query_canceled error, raised when `statement_timeout` is reached.
+const QUERY_CANCELED: &str = "57014";
 
-pub(crate) struct Paginated<U> {
+/// A page of records, and whether there are more earlier or later.
+pub(crate) struct KeysetPage<U> {
     pub(crate) records: Vec<U>,
+    pub(crate) has_earlier: bool,
+    pub(crate) has_later: bool,
+}
+
+/// A page of search results, and how many there are, counted up to [`SEARCH_COUNT_LIMIT`] + 1.
+pub(crate) struct SearchResults {
+    pub(crate) page: KeysetPage<Message>,
     pub(crate) total: i64,
-    pub(crate) page_count: i64,
 }
 
-pub(crate) async fn channels(db: &Database) -> Vec<ServerChannel> {
+impl SearchResults {
+    /// Whether there are more than [`SEARCH_COUNT_LIMIT`] results, which were not counted.
+    pub(crate) fn total_capped(&self) -> bool {
+        self.total > SEARCH_COUNT_LIMIT
+    }
+}
+
+impl Default for SearchResults {
+    fn default() -> Self {
+        Self {
+            page: KeysetPage {
+                records: vec![],
+                has_earlier: false,
+                has_later: false,
+            },
+            total: 0,
+        }
+    }
+}
+
+/// A search result, or a line around results if `context`. Lines of a block follow each other in
+/// their channel.
+pub(crate) struct SearchLine<'a> {
+    pub(crate) message: &'a Message,
+    pub(crate) context: bool,
+    pub(crate) block: usize,
+}
+
+/// Where a page starts, relative to the message with the given id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Cursor {
+    After(i32),
+    Before(i32),
+}
+
+impl Cursor {
+    /// From the `after` and `before` query parameters, `after` first.
+    pub(crate) fn new(after: Option<i32>, before: Option<i32>) -> Option<Self> {
+        match (after, before) {
+            (Some(id), _) => Some(Cursor::After(id)),
+            (None, Some(id)) => Some(Cursor::Before(id)),
+            (None, None) => None,
+        }
+    }
+
+    /// The `after` and `before` query parameters.
+    pub(crate) fn params(&self) -> (Option<i32>, Option<i32>) {
+        match *self {
+            Cursor::After(id) => (Some(id), None),
+            Cursor::Before(id) => (None, Some(id)),
+        }
+    }
+}
+
+/// Statistics of a channel, in the display time zone.
+pub(crate) struct ChannelStats {
+    /// Messages per weekday, from Monday, and hour.
+    pub(crate) heatmap: [[i64; 24]; 7],
+    /// In chronological order.
+    pub(crate) months: Vec<MonthStats>,
+    pub(crate) busiest_days: Vec<(Day, i64)>,
+    /// Most talkative first.
+    pub(crate) nicks: Vec<NickStats>,
+    /// Most recent first.
+    pub(crate) links: Vec<Message>,
+}
+
+pub(crate) struct MonthStats {
+    pub(crate) month: chrono::NaiveDate,
+    pub(crate) messages: i64,
+    pub(crate) links: i64,
+    pub(crate) joins: i64,
+    pub(crate) parts: i64,
+}
+
+pub(crate) struct NickStats {
+    pub(crate) nick: String,
+    pub(crate) messages: i64,
+    pub(crate) first_seen: Datetime,
+    pub(crate) last_seen: Datetime,
+    /// Messages in each of the last [`STATS_NICK_MONTHS`] months of the channel, in UTC.
+    pub(crate) recent: Vec<i64>,
+}
+
+pub(crate) async fn channels(db: &Database, viewer: &Viewer) -> Vec<ServerChannel> {
     // language=sql
-    sqlx::query!(r#"SELECT "channel" FROM all_channels()"#)
-        .fetch_all(db)
-        .await
-        .unwrap_or_default()
-        .iter()
-        .filter_map(|s| ServerChannel::from_str(s.channel.as_ref().unwrap()).ok())
-        .collect()
+    sqlx::query!(
+        r#"
+        SELECT "name" FROM "channel"
+        WHERE (NOT "private" OR $1) AND NOT ("name" = ANY($2))
+          AND EXISTS(SELECT FROM "message" WHERE "channel" = "name")
+        ORDER BY "name"
+    "#,
+        viewer.user.is_some(),
+        &viewer.denied
+    )
+    .fetch_all(db)
+    .await
+    .unwrap_or_default()
+    .iter()
+    .filter_map(|s| ServerChannel::from_str(&s.name).ok())
+    .collect()
 }
 
-pub(crate) async fn channel_exists(db: &Database, sc: &ServerChannel) -> bool {
+/// Whether `viewer` may see `sc`. Unknown channels are not visible.
+pub(crate) async fn channel_visible(db: &Database, sc: &ServerChannel, viewer: &Viewer) -> bool {
     // language=sql
     sqlx::query!(
-        r#"SELECT FROM "message" WHERE "channel" = $1 LIMIT 1"#,
-        sc.to_string()
+        r#"SELECT FROM "channel" WHERE "name" = $1 AND (NOT "private" OR $2) AND NOT ("name" = ANY($3))"#,
+        sc.to_string(),
+        viewer.user.is_some(),
+        &viewer.denied
     )
     .fetch_optional(db)
     .await
@@ -46,6 +167,7 @@ pub(crate) async fn channel_info(
     db: &Database,
     sc: &ServerChannel,
     before: &Day,
+    tz: &Tz,
 ) -> Option<ChannelInfo> {
     let channel = sc.to_string();
     // language=sql
@@ -56,41 +178,119 @@ pub(crate) async fn channel_info(
                 WHERE "channel" = $1 AND "opcode" = 'topic' AND coalesce("payload", '') != '' AND "timestamp" < $3
                 ORDER BY "timestamp" DESC LIMIT 1) "topic?:Message"
         FROM "ts" GROUP BY 1, 2, 3 LIMIT 1
-    "#, &channel, HARD_NICK_LIMIT as i64, before.succ().midnight())
+    "#, &channel, HARD_NICK_LIMIT as i64, before.succ().midnight(tz))
         .fetch_optional(db)
         .await
         .unwrap()
         .map(|r| ChannelInfo {
             sc: sc.clone(),
-            first_day: r.first.into(),
-            last_day: r.last.into(),
+            first_day: Day::at(&r.first, tz),
+            last_day: Day::at(&r.last, tz),
             topic: r.topic,
             nicks: r.nicks.into_iter().collect(),
         })
 }
 
-pub(crate) async fn messages_channel_day(
-    db: &Database,
+/// Messages of `sc` from the start of `from` to the end of `to`, as a stream rather than one page
+/// at a time.
+pub(crate) fn messages_channel_days<'a>(
+    db: &'a Database,
     sc: &ServerChannel,
-    day: &Day,
-) -> Vec<Message> {
+    from: &Day,
+    to: &Day,
+    tz: &Tz,
+) -> BoxStream<'a, sqlx::Result<Message>> {
     // language=sql
     sqlx::query_as!(
         Message,
         r#"
         SELECT * FROM "message"
         WHERE "channel" = $1 AND "timestamp" >= $2 AND "timestamp" < $3
-        ORDER BY "timestamp"
-        LIMIT $4
+        ORDER BY "timestamp", "id"
     "#,
         sc.to_string(),
-        day.midnight(),
-        day.succ().midnight(),
-        HARD_MESSAGE_LIMIT as i64
+        from.midnight(tz),
+        to.succ().midnight(tz),
     )
-    .fetch_all(db)
-    .await
-    .unwrap()
+    .fetch(db)
+}
+
+/// Messages of `sc` from `from` to `to` (excluded, or until now if missing), one page at a time,
+/// in chronological order. The first page starts at `from`.
+pub(crate) async fn messages_channel_range(
+    db: &Database,
+    sc: &ServerChannel,
+    from: &Datetime,
+    to: Option<&Datetime>,
+    cursor: Option<Cursor>,
+) -> KeysetPage<Message> {
+    // One more, to tell whether there is a next page.
+    let limit = RANGE_PAGE_SIZE as i64 + 1;
+    let mut records = match cursor {
+        None | Some(Cursor::After(_)) => {
+            let after = match cursor {
+                Some(Cursor::After(id)) => Some(id),
+                _ => None,
+            };
+            // language=sql
+            sqlx::query_as!(
+                Message,
+                r#"
+                SELECT * FROM "message"
+                WHERE "channel" = $1 AND "timestamp" >= $2 AND ($3::timestamptz IS NULL OR "timestamp" < $3)
+                  AND ($4::int IS NULL OR ("timestamp", "id") > (SELECT "timestamp", "id" FROM "message" WHERE "id" = $4))
+                ORDER BY "timestamp", "id"
+                LIMIT $5
+            "#,
+                sc.to_string(),
+                from,
+                to,
+                after,
+                limit
+            )
+            .fetch_all(db)
+            .await
+            .unwrap()
+        }
+        Some(Cursor::Before(before)) => {
+            // language=sql
+            sqlx::query_as!(
+                Message,
+                r#"
+                SELECT * FROM "message"
+                WHERE "channel" = $1 AND "timestamp" >= $2 AND ($3::timestamptz IS NULL OR "timestamp" < $3)
+                  AND ("timestamp", "id") < (SELECT "timestamp", "id" FROM "message" WHERE "id" = $4)
+                ORDER BY "timestamp" DESC, "id" DESC
+                LIMIT $5
+            "#,
+                sc.to_string(),
+                from,
+                to,
+                before,
+                limit
+            )
+            .fetch_all(db)
+            .await
+            .unwrap()
+        }
+    };
+    let more = records.len() > RANGE_PAGE_SIZE;
+    records.truncate(RANGE_PAGE_SIZE);
+    match cursor {
+        Some(Cursor::Before(_)) => {
+            records.reverse();
+            KeysetPage {
+                records,
+                has_earlier: more,
+                has_later: true,
+            }
+        }
+        _ => KeysetPage {
+            records,
+            has_earlier: cursor.is_some(),
+            has_later: more,
+        },
+    }
 }
 
 pub(crate) async fn channel_month_index(
@@ -98,6 +298,7 @@ pub(crate) async fn channel_month_index(
     sc: &ServerChannel,
     year: i32,
     month: u32,
+    tz: &Tz,
 ) -> HashSet<u32> {
     let from: Day = chrono::NaiveDate::from_ymd_opt(year, month, 1)
         .unwrap_or_default()
@@ -108,14 +309,15 @@ pub(crate) async fn channel_month_index(
     // language=sql
     sqlx::query!(
         r#"
-        SELECT DISTINCT EXTRACT(DAY FROM "timestamp")::smallint "day!"
+        SELECT DISTINCT EXTRACT(DAY FROM "timestamp" AT TIME ZONE $4)::smallint "day!"
         FROM "message"
         WHERE "channel" = $1 AND ("opcode" IS NULL OR "opcode" = 'me')
         AND "timestamp" >= $2 AND "timestamp" < $3
         "#,
         sc.to_string(),
-        from.midnight(),
-        to.midnight()
+        from.midnight(tz),
+        to.midnight(tz),
+        tz.name()
     )
     .fetch_all(db)
     .await
@@ -125,70 +327,341 @@ pub(crate) async fn channel_month_index(
     .collect()
 }
 
-pub(crate) async fn channel_search(
-    db: &Database,
-    sc: &ServerChannel,
-    query: &str,
-    page: i64,
-) -> Paginated<Message> {
-    // Try to find nick:<something> to build a non-empty nick filter.
-    lazy_static! {
-        static ref NICK: regex::Regex =
-            regex::Regex::new(r#"\b(nick:[A-Za-z_0-9|.`\*-]+)"#).unwrap();
+/// The full-text query of `text` in the configurations of any of `channels`, and whether it is the
+/// same in all of them. Unlike the query of each channel, it is a constant the planner estimates the
+/// matches of, to scan the index for rare words, or the most recent messages for common ones.
+async fn text_search_query(db: &Database, channels: &[String], text: &str) -> (String, bool) {
+    if text.is_empty() {
+        return (String::new(), true);
     }
-    let (query, nick_filter) = if let Some(mat) = NICK.find(query) {
-        let mut query = query.to_owned();
-        query.replace_range(mat.range(), "");
-        (
-            query.trim().to_string(),
-            mat.as_str()[5..].replace('*', "%"),
-        )
-    } else {
-        (query.trim().to_string(), "".to_string())
-    };
-    if query.is_empty() && nick_filter.is_empty() {
-        return Paginated {
-            page_count: 0,
-            records: vec![],
-            total: 0,
-        };
+    let queries: Vec<String> = sqlx::query_scalar!(
+        r#"SELECT DISTINCT websearch_to_tsquery("search_config", $2)::text "query!" FROM "channel" WHERE "name" = ANY($1)"#,
+        channels,
+        text
+    )
+    .fetch_all(db)
+    .await
+    .unwrap();
+    let same = queries.len() <= 1;
+    let query = queries
+        .into_iter()
+        // Only stop words: nothing to match.
+        .filter(|q| !q.is_empty())
+        .map(|q| format!("({q})"))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    (query, same)
+}
+
+/// Searches the messages of `channels`, most recent first, from the page after or before `cursor`.
+pub(crate) async fn search(
+    db: &Database,
+    channels: &[String],
+    query: &SearchQuery,
+    cursor: Option<Cursor>,
+    tz: &Tz,
+) -> Result<SearchResults, QueryError> {
+    if query.is_empty() {
+        return Ok(SearchResults::default());
     }
-    let per_page = SEARCH_PAGE_SIZE as i64;
-    let offset = (page - 1) * per_page;
+    let (since, until) = query.span(tz);
+    let (text_query, same_text_query) = text_search_query(db, channels, &query.text).await;
+    // One more, to tell whether there is a next page.
+    let limit = SEARCH_PAGE_SIZE as i64 + 1;
     struct Record {
         message: Message,
         headline: String,
         total: i64,
     }
+    // Any visitor can search, eg. with a pathological regular expression: do not let it hog one of
+    // the few connections for long.
+    let mut tx = db.begin().await.unwrap();
     // language=sql
-    let rows = sqlx::query_as!(Record, r#"
-        WITH "query" AS (
-            SELECT row("message".*) "message!:Message",
-                   ts_headline('english', "line", plainto_tsquery('english', $2), U&'StartSel=\E000, StopSel=\E001') "headline!"
-            FROM "message"
-            WHERE "channel" || '' = $1
-              AND coalesce("opcode", '') = ''
-              AND CASE WHEN $2 = '' THEN TRUE ELSE to_tsvector('english', "nick" || ' ' || "line") @@ plainto_tsquery('english', $2) END
-              AND CASE WHEN $5 = '' THEN TRUE ELSE "nick" LIKE $5 END
-            ORDER BY "timestamp" DESC
-        )
-        SELECT *, COUNT(*) OVER () "total!"
-        FROM "query" t LIMIT $3 OFFSET $4
-"#, sc.to_string(), &query, per_page, offset, nick_filter)
-        .fetch_all(db)
+    sqlx::query!(r#"SET LOCAL statement_timeout = '5s'"#)
+        .execute(&mut tx)
         .await
         .unwrap();
+    // Older results, from the most recent one, or newer results, from the oldest one.
+    let rows = match cursor {
+        None | Some(Cursor::Before(_)) => {
+            let before = match cursor {
+                Some(Cursor::Before(id)) => Some(id),
+                _ => None,
+            };
+            // language=sql
+            sqlx::query_as!(Record, r#"
+            WITH "matches" AS NOT MATERIALIZED (
+                SELECT "message"."id", "timestamp", c."search_config"
+                FROM "message"
+                         JOIN "message_search" s USING ("id")
+                         JOIN "channel" c ON c."name" = s."channel"
+                -- Both, for the planner to pick either the full-text or the trigram index.
+                WHERE s."channel" = ANY($1)
+                  AND "message"."channel" = ANY($1)
+                  AND ($11::text IS NULL OR coalesce("line", "payload") ~* $11)
+                  AND coalesce("opcode", '') = ANY($5)
+                  AND CASE WHEN $2 = '' THEN TRUE ELSE s."document" @@ $13::text::tsquery AND ($14 OR s."document" @@ websearch_to_tsquery(c."search_config", $2)) END
+                  AND (cardinality($6::text[]) = 0 OR "nick" LIKE ANY($6))
+                  AND NOT coalesce("nick" LIKE ANY($7), FALSE)
+                  AND ($8::timestamptz IS NULL OR "timestamp" >= $8)
+                  AND ($9::timestamptz IS NULL OR "timestamp" < $9)
+                  AND (NOT $10 OR "line" ~ 'https?://')
+            ), "page" AS (
+                SELECT * FROM "matches"
+                WHERE $4::int IS NULL OR ("timestamp", "id") < (SELECT "timestamp", "id" FROM "message" WHERE "id" = $4)
+                ORDER BY "timestamp" DESC, "id" DESC
+                LIMIT $3
+            )
+            SELECT row("message".*) "message!:Message",
+                   CASE WHEN $11::text IS NULL
+                       THEN ts_headline("page"."search_config", coalesce("line", "payload", ''), websearch_to_tsquery("page"."search_config", $2), U&'StartSel=\E000, StopSel=\E001')
+                       ELSE regexp_replace(coalesce("line", "payload", ''), $11, U&'\E000' || '\&' || U&'\E001', 'gi') END "headline!",
+                   (SELECT count(*) FROM (SELECT FROM "matches" LIMIT $12) "capped") "total!"
+            FROM "page" JOIN "message" USING ("id")
+            ORDER BY "page"."timestamp" DESC, "page"."id" DESC
+"#, channels, &query.text, limit, before, &query.opcodes, &query.nicks,
+                &query.excluded_nicks, since, until, query.has_link, query.pattern, SEARCH_COUNT_LIMIT + 1,
+                &text_query, same_text_query)
+                .fetch_all(&mut tx)
+                .await
+        }
+        Some(Cursor::After(after)) => {
+            // language=sql
+            sqlx::query_as!(Record, r#"
+            WITH "matches" AS NOT MATERIALIZED (
+                SELECT "message"."id", "timestamp", c."search_config"
+                FROM "message"
+                         JOIN "message_search" s USING ("id")
+                         JOIN "channel" c ON c."name" = s."channel"
+                -- Both, for the planner to pick either the full-text or the trigram index.
+                WHERE s."channel" = ANY($1)
+                  AND "message"."channel" = ANY($1)
+                  AND ($11::text IS NULL OR coalesce("line", "payload") ~* $11)
+                  AND coalesce("opcode", '') = ANY($5)
+                  AND CASE WHEN $2 = '' THEN TRUE ELSE s."document" @@ $13::text::tsquery AND ($14 OR s."document" @@ websearch_to_tsquery(c."search_config", $2)) END
+                  AND (cardinality($6::text[]) = 0 OR "nick" LIKE ANY($6))
+                  AND NOT coalesce("nick" LIKE ANY($7), FALSE)
+                  AND ($8::timestamptz IS NULL OR "timestamp" >= $8)
+                  AND ($9::timestamptz IS NULL OR "timestamp" < $9)
+                  AND (NOT $10 OR "line" ~ 'https?://')
+            ), "page" AS (
+                SELECT * FROM "matches"
+                WHERE ("timestamp", "id") > (SELECT "timestamp", "id" FROM "message" WHERE "id" = $4)
+                ORDER BY "timestamp" ASC, "id" ASC
+                LIMIT $3
+            )
+            SELECT row("message".*) "message!:Message",
+                   CASE WHEN $11::text IS NULL
+                       THEN ts_headline("page"."search_config", coalesce("line", "payload", ''), websearch_to_tsquery("page"."search_config", $2), U&'StartSel=\E000, StopSel=\E001')
+                       ELSE regexp_replace(coalesce("line", "payload", ''), $11, U&'\E000' || '\&' || U&'\E001', 'gi') END "headline!",
+                   (SELECT count(*) FROM (SELECT FROM "matches" LIMIT $12) "capped") "total!"
+            FROM "page" JOIN "message" USING ("id")
+            ORDER BY "page"."timestamp" ASC, "page"."id" ASC
+"#, channels, &query.text, limit, after, &query.opcodes, &query.nicks,
+                &query.excluded_nicks, since, until, query.has_link, query.pattern, SEARCH_COUNT_LIMIT + 1,
+                &text_query, same_text_query)
+                .fetch_all(&mut tx)
+                .await
+        }
+    };
+    let rows = match rows {
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some(INVALID_REGEX) => {
+            let message = error.message();
+            let reason = message.trim_start_matches("invalid regular expression: ");
+            return Err(QueryError::InvalidRegex(reason.to_string()));
+        }
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some(QUERY_CANCELED) => {
+            return Err(QueryError::TimedOut);
+        }
+        rows => rows.unwrap(),
+    };
     let total = rows.first().map(|r| r.total).unwrap_or(0);
-    let records = rows
+    let mut records: Vec<Message> = rows
         .into_iter()
-        .map(|r| Message {
-            line: Some(r.headline),
-            ..r.message
+        .map(|r| match r.message.opcode.as_deref() {
+            Some("topic") => Message {
+                payload: Some(r.headline),
+                ..r.message
+            },
+            _ => Message {
+                line: Some(r.headline),
+                ..r.message
+            },
         })
         .collect();
-    Paginated {
-        page_count: (total as f64 / per_page as f64).ceil() as i64,
-        records,
-        total,
+    let more = records.len() > SEARCH_PAGE_SIZE as usize;
+    records.truncate(SEARCH_PAGE_SIZE as usize);
+    let page = match cursor {
+        Some(Cursor::After(_)) => {
+            records.reverse();
+            KeysetPage {
+                records,
+                has_earlier: true,
+                has_later: more,
+            }
+        }
+        _ => KeysetPage {
+            records,
+            has_earlier: more,
+            has_later: cursor.is_some(),
+        },
+    };
+    Ok(SearchResults { page, total })
+}
+
+/// Up to `lines` messages before and after each of `hits` in its channel, with the id of the hit
+/// they are around. Messages around several hits are listed for each of them.
+pub(crate) async fn search_context(
+    db: &Database,
+    hits: &[Message],
+    lines: u8,
+) -> Vec<(i32, Message)> {
+    if hits.is_empty() || lines == 0 {
+        return vec![];
+    }
+    struct Record {
+        hit: i32,
+        message: Message,
+    }
+    let ids: Vec<i32> = hits.iter().map(|m| m.id).collect();
+    // language=sql
+    sqlx::query_as!(
+        Record,
+        r#"
+        SELECT "hit"."id" "hit!", row(m.*) "message!:Message"
+        FROM unnest($1::int[]) "hit"("id")
+                 JOIN "message" h ON h."id" = "hit"."id"
+                 CROSS JOIN LATERAL (
+            (SELECT * FROM "message"
+             WHERE "channel" = h."channel" AND ("timestamp", "id") < (h."timestamp", h."id")
+             ORDER BY "timestamp" DESC, "id" DESC
+             LIMIT $2)
+            UNION ALL
+            (SELECT * FROM "message"
+             WHERE "channel" = h."channel" AND ("timestamp", "id") > (h."timestamp", h."id")
+             ORDER BY "timestamp", "id"
+             LIMIT $2)
+            ) m
+    "#,
+        &ids,
+        lines.min(SEARCH_CONTEXT_LIMIT) as i64
+    )
+    .fetch_all(db)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.hit, r.message))
+    .collect()
+}
+
+/// Statistics of `sc`, from the activity counted as messages are saved.
+pub(crate) async fn channel_stats(db: &Database, sc: &ServerChannel, tz: &Tz) -> ChannelStats {
+    let channel = sc.to_string();
+    let tz = tz.name();
+    let (hours, months, busiest_days, nicks, links) = tokio::join!(
+        // language=sql
+        sqlx::query!(
+            r#"
+            SELECT extract(ISODOW FROM "hour" AT TIME ZONE $2)::int "weekday!",
+                   extract(HOUR FROM "hour" AT TIME ZONE $2)::int "hour!",
+                   sum("messages")::bigint "messages!"
+            FROM "channel_activity" WHERE "channel" = $1
+            GROUP BY 1, 2
+        "#,
+            &channel,
+            tz
+        )
+        .fetch_all(db),
+        // language=sql
+        sqlx::query_as!(
+            MonthStats,
+            r#"
+            SELECT date_trunc('month', "hour" AT TIME ZONE $2)::date "month!",
+                   sum("messages")::bigint "messages!", sum("links")::bigint "links!",
+                   sum("joins")::bigint "joins!", sum("parts")::bigint "parts!"
+            FROM "channel_activity" WHERE "channel" = $1
+            GROUP BY 1 ORDER BY 1
+        "#,
+            &channel,
+            tz
+        )
+        .fetch_all(db),
+        // language=sql
+        sqlx::query!(
+            r#"
+            SELECT ("hour" AT TIME ZONE $2)::date "day!", sum("messages")::bigint "messages!"
+            FROM "channel_activity" WHERE "channel" = $1
+            GROUP BY 1 ORDER BY 2 DESC, 1 DESC
+            LIMIT $3
+        "#,
+            &channel,
+            tz,
+            STATS_BUSIEST_DAYS
+        )
+        .fetch_all(db),
+        // language=sql
+        sqlx::query_as!(
+            NickStats,
+            r#"
+            WITH "months" AS (
+                SELECT generate_series(max("month") - interval '1 month' * ($3 - 1), max("month"), interval '1 month') "month"
+                FROM "channel_nick_activity" WHERE "channel" = $1
+            )
+            SELECT n."nick" "nick!", sum(n."messages")::bigint "messages!",
+                   min(n."first_seen") "first_seen!", max(n."last_seen") "last_seen!",
+                   array(SELECT coalesce(a."messages", 0)::bigint FROM "months" m
+                         LEFT JOIN "channel_nick_activity" a
+                             ON a."channel" = $1 AND a."nick" = n."nick" AND a."month" = m."month"
+                         ORDER BY m."month") "recent!"
+            FROM "channel_nick_activity" n WHERE n."channel" = $1
+            GROUP BY 1 HAVING sum(n."messages") > 0
+            ORDER BY 2 DESC, 1
+            LIMIT $2
+        "#,
+            &channel,
+            STATS_TOP_NICKS,
+            STATS_NICK_MONTHS as i32
+        )
+        .fetch_all(db),
+        // Only in the last hours with links, rather than the whole channel. Limiting each hour
+        // keeps the planner from joining all messages with the hours instead.
+        // language=sql
+        sqlx::query_as!(
+            Message,
+            r#"
+            SELECT m.* FROM (
+                SELECT "hour" FROM "channel_activity"
+                WHERE "channel" = $1 AND "links" > 0
+                ORDER BY "hour" DESC LIMIT $2
+            ) h
+            CROSS JOIN LATERAL (
+                SELECT * FROM "message"
+                WHERE "channel" = $1 AND "timestamp" >= h."hour" AND "timestamp" < h."hour" + interval '1 hour'
+                  AND "line" ~ 'https?://'
+                ORDER BY "timestamp" DESC, "id" DESC
+                LIMIT $2
+            ) m
+            ORDER BY m."timestamp" DESC, m."id" DESC
+            LIMIT $2
+        "#,
+            &channel,
+            STATS_RECENT_LINKS
+        )
+        .fetch_all(db),
+    );
+    let mut heatmap = [[0; 24]; 7];
+    for r in hours.unwrap() {
+        heatmap[r.weekday as usize - 1][r.hour as usize] = r.messages;
+    }
+    ChannelStats {
+        heatmap,
+        months: months.unwrap(),
+        busiest_days: busiest_days
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.day.into(), r.messages))
+            .collect(),
+        nicks: nicks.unwrap(),
+        links: links.unwrap(),
     }
 }
@@ -0,0 +1,216 @@
+use chrono_tz::Tz;
+use rocket::http::{ContentType, Header};
+
+use ircjournal::{irssi::Irssi, message_to_line, model::ServerChannel, weechat::Weechat, Message};
+
+use crate::Day;
+
+#[derive(Debug, Clone, Copy, PartialEq, FromFormField)]
+pub enum ExportFormat {
+    /// Human-readable, irssi-like.
+    Plain,
+    /// Lines WeeChat would log, that ircj-watch can read back.
+    Weechat,
+    /// One JSON message per line.
+    Jsonl,
+    Csv,
+}
+
+/// A streamed export, with a file name for browsers to save it as.
+#[derive(Responder)]
+pub struct Export<R> {
+    inner: R,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+const CSV_HEADER: &str = "id,channel,nick,line,opcode,oper_nick,payload,timestamp";
+
+impl<R> Export<R> {
+    pub(crate) fn new(
+        inner: R,
+        format: ExportFormat,
+        sc: &ServerChannel,
+        from: &Day,
+        to: &Day,
+        attachment: bool,
+    ) -> Self {
+        let (content_type, extension) = match format {
+            ExportFormat::Plain | ExportFormat::Weechat => (ContentType::Plain, "txt"),
+            ExportFormat::Jsonl => (ContentType::new("application", "x-ndjson"), "jsonl"),
+            ExportFormat::Csv => (ContentType::CSV, "csv"),
+        };
+        let days = if from == to {
+            from.ymd()
+        } else {
+            format!("{}_{}", from.ymd(), to.ymd())
+        };
+        let name: String = format!("{}-{}-{}.{}", sc.server, sc.channel, days, extension)
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '#' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        let disposition = if attachment { "attachment" } else { "inline" };
+        Self {
+            inner,
+            content_type,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("{}; filename=\"{}\"", disposition, name),
+            ),
+        }
+    }
+}
+
+/// Formats messages one line at a time, in order.
+pub(crate) struct Exporter {
+    format: ExportFormat,
+    channel: String,
+    tz: Tz,
+    /// The day of the previous message, for irssi-like day markers.
+    day: Option<Day>,
+}
+
+impl Exporter {
+    pub(crate) fn new(format: ExportFormat, sc: &ServerChannel, tz: &Tz) -> Self {
+        Self {
+            format,
+            channel: sc.channel.clone(),
+            tz: *tz,
+            day: None,
+        }
+    }
+
+    /// What comes before the first message, if anything.
+    pub(crate) fn header(&self) -> Option<String> {
+        match self.format {
+            ExportFormat::Csv => Some(format!("{}\n", CSV_HEADER)),
+            _ => None,
+        }
+    }
+
+    /// The lines for `message`, including the line break. Empty if there is nothing to write.
+    pub(crate) fn lines(&mut self, message: &Message) -> String {
+        let local = message.timestamp.with_timezone(&self.tz).naive_local();
+        match self.format {
+            ExportFormat::Plain => {
+                let day = Day::from(local.date());
+                let marker = if self.day.as_ref() != Some(&day) {
+                    format!("{}\n", Irssi::format_day_changed(&day.0))
+                } else {
+                    "".to_string()
+                };
+                self.day = Some(day);
+                match Irssi::format_line(&self.channel, &local.time(), &message_to_line(message)) {
+                    Some(line) => format!("{}{}\n", marker, line),
+                    None => marker,
+                }
+            }
+            ExportFormat::Weechat => {
+                Weechat::format_line(&self.channel, &local, &message_to_line(message))
+                    .map(|line| format!("{}\n", line))
+                    .unwrap_or_default()
+            }
+            ExportFormat::Jsonl => format!("{}\n", serde_json::to_string(message).unwrap()),
+            ExportFormat::Csv => {
+                let id = message.id.to_string();
+                let timestamp = message.timestamp.to_rfc3339();
+                let fields = [
+                    Some(id.as_str()),
+                    message.channel.as_deref(),
+                    message.nick.as_deref(),
+                    message.line.as_deref(),
+                    message.opcode.as_deref(),
+                    message.oper_nick.as_deref(),
+                    message.payload.as_deref(),
+                    Some(timestamp.as_str()),
+                ];
+                let row: Vec<String> = fields
+                    .iter()
+                    .map(|field| csv_field(field.unwrap_or_default()))
+                    .collect();
+                format!("{}\n", row.join(","))
+            }
+        }
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[test]
+fn test_exporter() {
+    use ircjournal::{line_to_new_message, IrcLine};
+    let sc = ServerChannel::new("libera", "#chan");
+    let message = |ts: &str, line: IrcLine| {
+        let timestamp = chrono::DateTime::parse_from_rfc3339(ts)
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let m = line_to_new_message(line, &sc, timestamp).unwrap();
+        Message {
+            id: 1,
+            channel: m.channel,
+            nick: m.nick,
+            line: m.line,
+            opcode: m.opcode,
+            oper_nick: m.oper_nick,
+            payload: m.payload,
+            timestamp: m.timestamp,
+            occurrence: m.occurrence,
+        }
+    };
+    let hello = message(
+        "2020-01-25T23:31:14Z",
+        IrcLine::Message {
+            nick: "zopieux".to_string(),
+            line: "hello, \"world\"".to_string(),
+        },
+    );
+    let joined = message(
+        "2020-01-26T00:01:00Z",
+        IrcLine::Joined {
+            nick: "haileda".to_string(),
+        },
+    );
+    let export = |format, tz| {
+        let mut exporter = Exporter::new(format, &sc, &tz);
+        let mut out = exporter.header().unwrap_or_default();
+        out.push_str(&exporter.lines(&hello));
+        out.push_str(&exporter.lines(&joined));
+        out
+    };
+
+    assert_eq!(
+        export(ExportFormat::Plain, chrono_tz::UTC),
+        "--- Day changed Sat Jan 25 2020\n\
+         23:31:14 < zopieux> hello, \"world\"\n\
+         --- Day changed Sun Jan 26 2020\n\
+         00:01:00 -!- haileda [] has joined #chan\n"
+    );
+    assert_eq!(
+        export(ExportFormat::Plain, chrono_tz::Europe::Paris),
+        "--- Day changed Sun Jan 26 2020\n\
+         00:31:14 < zopieux> hello, \"world\"\n\
+         01:01:00 -!- haileda [] has joined #chan\n"
+    );
+    assert_eq!(
+        export(ExportFormat::Weechat, chrono_tz::UTC),
+        "2020-01-25 23:31:14\tzopieux\thello, \"world\"\n\
+         2020-01-26 00:01:00\t-->\thaileda () has joined #chan\n"
+    );
+    assert_eq!(
+        export(ExportFormat::Csv, chrono_tz::UTC),
+        "id,channel,nick,line,opcode,oper_nick,payload,timestamp\n\
+         1,libera/#chan,zopieux,\"hello, \"\"world\"\"\",,,,2020-01-25T23:31:14+00:00\n\
+         1,libera/#chan,haileda,,joined,,,2020-01-26T00:01:00+00:00\n"
+    );
+    assert!(export(ExportFormat::Jsonl, chrono_tz::UTC)
+        .starts_with("{\"id\":1,\"channel\":\"libera/#chan\",\"nick\":\"zopieux\","));
+}
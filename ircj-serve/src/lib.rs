@@ -2,10 +2,19 @@
 extern crate rocket;
 
 use chrono::{Datelike, NaiveDate};
+use chrono_tz::Tz;
 use ircjournal::model::{Datetime, Message, ServerChannel};
-use std::{collections::HashSet, str::FromStr};
-
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+pub mod api;
+pub mod auth;
 mod db;
+pub mod export;
+mod query;
 pub mod route;
 mod route_adapt;
 mod route_static;
@@ -14,8 +23,36 @@ pub mod watch;
 
 pub(crate) type Nicks = HashSet<String>;
 
-#[derive(Debug)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct Config {
+    pub db: String,
+    /// Request header set by a trusted reverse-proxy to the name of the user it authenticated.
+    /// Only enable behind a proxy that always sets or strips it.
+    pub auth_header: Option<String>,
+    /// Users who can log in, with the argon2 hash of their password.
+    pub users: HashMap<String, String>,
+    /// Channels only the listed users can see.
+    pub acl: HashMap<String, Vec<String>>,
+}
+
+/// Who is browsing. Private channels are only visible to logged in users.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Viewer {
+    pub(crate) user: Option<String>,
+    /// Channels restricted to other users.
+    pub(crate) denied: Vec<String>,
+}
+
+/// A new message and the channel nicks, to be rendered for each live stream subscriber.
+pub type MessageEvent = (ServerChannel, Arc<(Message, Nicks)>);
+
+/// The time zone dates and times are displayed in, as picked by the visitor. Defaults to UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTz(pub(crate) Tz);
+
+#[derive(Debug, serde::Serialize)]
 pub struct ChannelInfo {
+    #[serde(rename = "channel")]
     pub(crate) sc: ServerChannel,
     pub(crate) first_day: Day,
     pub(crate) last_day: Day,
@@ -23,16 +60,21 @@ pub struct ChannelInfo {
     pub(crate) nicks: Nicks,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
 pub struct Day(pub(crate) chrono::NaiveDate);
 
 impl Day {
-    pub(crate) fn today() -> Self {
-        Self(chrono::Local::now().date_naive())
+    /// The day `ts` falls on in `tz`.
+    pub(crate) fn at(ts: &Datetime, tz: &Tz) -> Self {
+        Self(ts.with_timezone(tz).date_naive())
+    }
+
+    pub(crate) fn today(tz: &Tz) -> Self {
+        Self::at(&chrono::Utc::now(), tz)
     }
 
-    pub(crate) fn is_today_or_future(&self) -> bool {
-        Self::today().0 <= self.0
+    pub(crate) fn is_today_or_future(&self, tz: &Tz) -> bool {
+        Self::today(tz).0 <= self.0
     }
 
     pub(crate) fn succ(&self) -> Self {
@@ -43,11 +85,14 @@ impl Day {
         Self(self.0.pred_opt().unwrap_or_default())
     }
 
-    pub(crate) fn midnight(&self) -> Datetime {
-        Datetime::from_naive_utc_and_offset(
-            self.0.and_hms_opt(0, 0, 0).unwrap_or_default(),
-            chrono::Utc,
-        )
+    /// The start of the day in `tz`.
+    pub(crate) fn midnight(&self, tz: &Tz) -> Datetime {
+        self.start().to_utc(tz)
+    }
+
+    /// The start of the day, in any time zone.
+    pub(crate) fn start(&self) -> LocalTime {
+        LocalTime(self.0.and_hms_opt(0, 0, 0).unwrap_or_default())
     }
 
     pub(crate) fn ymd(&self) -> String {
@@ -62,10 +107,6 @@ impl Day {
         self.0.format("%d").to_string()
     }
 
-    pub(crate) fn month(&self) -> String {
-        self.0.format("%B").to_string()
-    }
-
     pub(crate) fn month_short(&self) -> String {
         self.0.format("%b").to_string()
     }
@@ -75,19 +116,28 @@ impl Day {
     }
 }
 
-impl From<Datetime> for Day {
-    fn from(ts: Datetime) -> Self {
-        Self(ts.date_naive())
+/// A date and time in the display time zone, written `YYYY-MM-DDTHH:MM` in URLs like HTML forms
+/// do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime(pub(crate) chrono::NaiveDateTime);
+
+impl LocalTime {
+    pub(crate) fn to_utc(self, tz: &Tz) -> Datetime {
+        ircjournal::local_to_utc(tz, self.0, None)
+    }
+
+    pub(crate) fn iso(&self) -> String {
+        self.0.format("%Y-%m-%dT%H:%M").to_string()
     }
 }
 
+/// A day, written `YYYY-MM-DD.txt` in URLs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextDay(pub(crate) Day);
+
 impl From<chrono::NaiveDate> for Day {
     fn from(ts: NaiveDate) -> Self {
-        Datetime::from_naive_utc_and_offset(
-            ts.and_hms_opt(0, 0, 0).unwrap_or_default(),
-            chrono::Utc,
-        )
-        .into()
+        Self(ts)
     }
 }
 
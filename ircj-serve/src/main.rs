@@ -3,16 +3,7 @@ extern crate rocket;
 use figment::providers::Format;
 use rocket::fairing::AdHoc;
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-pub struct Config {
-    pub db: String,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self { db: "".to_owned() }
-    }
-}
+use ircj_serve::Config;
 
 #[rocket::launch]
 async fn get_rocket() -> rocket::Rocket<rocket::Build> {
@@ -36,8 +27,10 @@ async fn get_rocket() -> rocket::Rocket<rocket::Build> {
                 )
             },
         ))
+        .attach(ircj_serve::auth::fairing())
         .attach(ircj_serve::watch::fairing())
         .register("/", ircj_serve::route::catchers())
         .mount("/static", ircj_serve::route::StaticFiles {})
         .mount("/", ircj_serve::route::routes())
+        .mount("/api/v1", ircj_serve::api::routes())
 }
@@ -0,0 +1,456 @@
+use chrono_tz::Tz;
+use std::fmt;
+
+use ircjournal::model::Datetime;
+
+use crate::Day;
+
+/// How the text of a query matches lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, FromFormField)]
+pub(crate) enum SearchMode {
+    /// Words in any form, `"phrases"`, `-exclusions` and `OR`.
+    #[default]
+    Text,
+    /// The text as is, eg. an identifier or a URL, anywhere in lines.
+    Substring,
+    /// A POSIX regular expression.
+    Regex,
+}
+
+impl SearchMode {
+    pub(crate) const ALL: [SearchMode; 3] = [Self::Text, Self::Substring, Self::Regex];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Substring => "substring",
+            Self::Regex => "regex",
+        }
+    }
+
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Self::Text => "Words",
+            Self::Substring => "Substring",
+            Self::Regex => "Regular expression",
+        }
+    }
+}
+
+/// A parsed search query. In text mode, words, `"phrases"`, `-exclusions` and `OR` are left for
+/// `websearch_to_tsquery`. In the other modes, they are a pattern lines must match, ignoring
+/// case. In all modes, filters become SQL predicates:
+///
+/// - `nick:zopieux`, `nick:zop*`: by one of these nicks, `-nick:bot` not by this one.
+/// - `before:2024-01-31`, `after:2024-01-01`, `on:2024-01-15`: by date, in the display time zone.
+/// - `is:message`, `is:action`, `is:topic`, `is:notice`: kinds of lines, plain messages if none.
+/// - `has:link`: lines with a URL.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SearchQuery {
+    /// Full-text search, in `websearch_to_tsquery` syntax.
+    pub(crate) text: String,
+    /// A PostgreSQL regular expression, in substring and regex modes.
+    pub(crate) pattern: Option<String>,
+    /// `LIKE` patterns.
+    pub(crate) nicks: Vec<String>,
+    pub(crate) excluded_nicks: Vec<String>,
+    /// First day included.
+    pub(crate) since: Option<Day>,
+    /// First day excluded.
+    pub(crate) until: Option<Day>,
+    /// Opcodes, the empty string for plain messages.
+    pub(crate) opcodes: Vec<String>,
+    pub(crate) has_link: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum QueryError {
+    UnterminatedQuote,
+    EmptyFilter(String),
+    InvalidDate(String),
+    UnknownValue {
+        filter: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+    NegatedFilter(String),
+    EmptyDateRange,
+    PatternTooLong,
+    InvalidRegex(String),
+    TimedOut,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::UnterminatedQuote => write!(f, "A quote is missing its closing \"."),
+            QueryError::EmptyFilter(filter) => {
+                write!(f, "{}: needs a value, eg. {}", filter, example(filter))
+            }
+            QueryError::InvalidDate(date) => {
+                write!(f, "{} is not a valid date, write it like 2024-01-31.", date)
+            }
+            QueryError::UnknownValue {
+                filter,
+                value,
+                expected,
+            } => write!(
+                f,
+                "{}:{} is not supported, use one of: {}.",
+                filter, value, expected
+            ),
+            QueryError::NegatedFilter(filter) => {
+                write!(f, "{}: cannot be excluded, only nick: can.", filter)
+            }
+            QueryError::EmptyDateRange => write!(f, "No day matches these dates."),
+            QueryError::PatternTooLong => write!(
+                f,
+                "The pattern is too long, it can have up to {} characters.",
+                PATTERN_MAX_LENGTH
+            ),
+            QueryError::InvalidRegex(error) => {
+                write!(f, "The regular expression is invalid: {}.", error)
+            }
+            QueryError::TimedOut => write!(
+                f,
+                "The search took too long, try a simpler or more specific one."
+            ),
+        }
+    }
+}
+
+fn example(filter: &str) -> &'static str {
+    match filter {
+        "nick" => "nick:zopieux",
+        "is" => "is:action",
+        "has" => "has:link",
+        _ => "on:2024-01-31",
+    }
+}
+
+const IS_VALUES: &str = "message, action, topic, notice";
+const HAS_VALUES: &str = "link";
+/// Longest substring or regular expression searched for, in characters.
+const PATTERN_MAX_LENGTH: usize = 200;
+
+/// Splits on spaces, except within quotes. Quotes are kept.
+fn tokenize(query: &str) -> Result<Vec<String>, QueryError> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if quoted {
+        return Err(QueryError::UnterminatedQuote);
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn nick_pattern(nick: &str) -> String {
+    nick.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+        .replace('*', "%")
+}
+
+/// Matches `text` literally, in a PostgreSQL regular expression.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn parse_day(date: &str) -> Result<Day, QueryError> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(Day)
+        .map_err(|_| QueryError::InvalidDate(date.to_string()))
+}
+
+impl SearchQuery {
+    pub(crate) fn parse(query: &str, mode: SearchMode) -> Result<Self, QueryError> {
+        let mut parsed = SearchQuery::default();
+        let mut text = vec![];
+        for token in tokenize(query)? {
+            let (negated, term) = match token.strip_prefix('-') {
+                Some(term) => (true, term),
+                None => (false, token.as_str()),
+            };
+            let (filter, value) = match term.split_once(':') {
+                Some((filter, value))
+                    if matches!(filter, "nick" | "before" | "after" | "on" | "is" | "has") =>
+                {
+                    (filter, value.trim_matches('"'))
+                }
+                // Words, phrases and eg. URLs.
+                _ => {
+                    text.push(token.clone());
+                    continue;
+                }
+            };
+            if value.is_empty() {
+                return Err(QueryError::EmptyFilter(filter.to_string()));
+            }
+            if negated && filter != "nick" {
+                return Err(QueryError::NegatedFilter(filter.to_string()));
+            }
+            match filter {
+                "nick" if negated => parsed.excluded_nicks.push(nick_pattern(value)),
+                "nick" => parsed.nicks.push(nick_pattern(value)),
+                "before" => parsed.until(parse_day(value)?),
+                "after" => parsed.since(parse_day(value)?.succ()),
+                "on" => {
+                    let day = parse_day(value)?;
+                    parsed.until(day.succ());
+                    parsed.since(day);
+                }
+                "is" => parsed.opcodes.push(
+                    match value {
+                        "message" => "",
+                        "action" => "me",
+                        "topic" => "topic",
+                        "notice" => "notice",
+                        _ => {
+                            return Err(QueryError::UnknownValue {
+                                filter: "is",
+                                value: value.to_string(),
+                                expected: IS_VALUES,
+                            })
+                        }
+                    }
+                    .to_string(),
+                ),
+                _ => match value {
+                    "link" => parsed.has_link = true,
+                    _ => {
+                        return Err(QueryError::UnknownValue {
+                            filter: "has",
+                            value: value.to_string(),
+                            expected: HAS_VALUES,
+                        })
+                    }
+                },
+            }
+        }
+        if let (Some(since), Some(until)) = (&parsed.since, &parsed.until) {
+            if since.0 >= until.0 {
+                return Err(QueryError::EmptyDateRange);
+            }
+        }
+        if parsed.opcodes.is_empty() {
+            parsed.opcodes.push("".to_string());
+        }
+        let text = text.join(" ");
+        // Quotes keep spaces in patterns.
+        let unquoted = match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            Some(unquoted) if !unquoted.contains('"') => unquoted,
+            _ => &text,
+        };
+        match mode {
+            SearchMode::Text => parsed.text = text,
+            _ if unquoted.is_empty() => {}
+            _ if unquoted.chars().count() > PATTERN_MAX_LENGTH => {
+                return Err(QueryError::PatternTooLong)
+            }
+            SearchMode::Substring => parsed.pattern = Some(escape_regex(unquoted)),
+            SearchMode::Regex => parsed.pattern = Some(unquoted.to_string()),
+        }
+        Ok(parsed)
+    }
+
+    fn since(&mut self, day: Day) {
+        if self.since.as_ref().is_none_or(|since| since.0 < day.0) {
+            self.since = Some(day);
+        }
+    }
+
+    fn until(&mut self, day: Day) {
+        if self.until.as_ref().is_none_or(|until| day.0 < until.0) {
+            self.until = Some(day);
+        }
+    }
+
+    /// Whether there is nothing to search for.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.text.is_empty()
+            && self.pattern.is_none()
+            && self.nicks.is_empty()
+            && self.excluded_nicks.is_empty()
+            && self.since.is_none()
+            && self.until.is_none()
+            && !self.has_link
+            && self.opcodes == [""]
+    }
+
+    /// The time span to search in, as instants.
+    pub(crate) fn span(&self, tz: &Tz) -> (Option<Datetime>, Option<Datetime>) {
+        (
+            self.since.as_ref().map(|day| day.midnight(tz)),
+            self.until.as_ref().map(|day| day.midnight(tz)),
+        )
+    }
+}
+
+#[cfg(test)]
+fn day(s: &str) -> Option<Day> {
+    Some(parse_day(s).unwrap())
+}
+
+#[test]
+fn test_tokenize() {
+    assert_eq!(tokenize("  ").unwrap(), Vec::<String>::new());
+    assert_eq!(
+        tokenize(r#"a "b c"  -"d e" nick:"f""#).unwrap(),
+        vec!["a", r#""b c""#, r#"-"d e""#, r#"nick:"f""#]
+    );
+    assert_eq!(tokenize(r#"a "b"#), Err(QueryError::UnterminatedQuote));
+}
+
+#[test]
+fn test_parse_text() {
+    let parse = |q| SearchQuery::parse(q, SearchMode::Text).unwrap();
+    assert_eq!(
+        parse(""),
+        SearchQuery::parse("  ", SearchMode::Text).unwrap()
+    );
+    assert!(parse("").is_empty());
+    assert_eq!(
+        parse(r#"rust  "borrow checker" -async OR tokio"#),
+        SearchQuery {
+            text: r#"rust "borrow checker" -async OR tokio"#.to_string(),
+            opcodes: vec!["".to_string()],
+            ..SearchQuery::default()
+        }
+    );
+    // Unknown filters are words, eg. URLs.
+    assert_eq!(
+        parse("https://example.com todo:").text,
+        "https://example.com todo:"
+    );
+}
+
+#[test]
+fn test_parse_filters() {
+    let parse = |q| SearchQuery::parse(q, SearchMode::Text).unwrap();
+    let q = parse("hello nick:zopieux nick:zop* -nick:some_bot is:action is:topic has:link");
+    assert_eq!(q.text, "hello");
+    assert_eq!(q.nicks, vec!["zopieux", "zop%"]);
+    assert_eq!(q.excluded_nicks, vec!["some\\_bot"]);
+    assert_eq!(q.opcodes, vec!["me", "topic"]);
+    assert!(q.has_link);
+    assert!(!q.is_empty());
+
+    let q = parse("after:2024-01-01 before:2024-01-31");
+    assert_eq!((q.since, q.until), (day("2024-01-02"), day("2024-01-31")));
+    let q = parse("on:2024-01-15 after:2024-01-01");
+    assert_eq!((q.since, q.until), (day("2024-01-15"), day("2024-01-16")));
+    let q = parse(r#"on:"2024-01-15""#);
+    assert_eq!((q.since, q.until), (day("2024-01-15"), day("2024-01-16")));
+    assert_eq!(
+        parse("on:2024-01-15").span(&chrono_tz::Europe::Paris),
+        (
+            Some(
+                chrono::DateTime::parse_from_rfc3339("2024-01-14T23:00:00Z")
+                    .unwrap()
+                    .into()
+            ),
+            Some(
+                chrono::DateTime::parse_from_rfc3339("2024-01-15T23:00:00Z")
+                    .unwrap()
+                    .into()
+            ),
+        )
+    );
+}
+
+#[test]
+fn test_parse_patterns() {
+    let pattern = |q, mode| SearchQuery::parse(q, mode).unwrap().pattern;
+    assert_eq!(pattern("0xdeadbeef", SearchMode::Text), None);
+    assert_eq!(
+        pattern("foo_bar::baz nick:zop", SearchMode::Substring),
+        Some("foo\\_bar\\:\\:baz".to_string())
+    );
+    assert_eq!(
+        pattern("https://a.b/?q=1", SearchMode::Substring),
+        Some("https\\:\\/\\/a\\.b\\/\\?q\\=1".to_string())
+    );
+    assert_eq!(
+        pattern(r#""a  b" nick:zop"#, SearchMode::Substring),
+        Some("a  b".to_string())
+    );
+    assert_eq!(
+        pattern(r#"-v "a" b"#, SearchMode::Substring),
+        Some(r#"\-v \"a\" b"#.to_string())
+    );
+    assert_eq!(
+        pattern(r"^0x[0-9a-f]{8}\b is:action", SearchMode::Regex),
+        Some(r"^0x[0-9a-f]{8}\b".to_string())
+    );
+    assert_eq!(pattern("nick:zop", SearchMode::Regex), None);
+    assert!(SearchQuery::parse("", SearchMode::Regex)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |q| {
+        SearchQuery::parse(q, SearchMode::Text)
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(error(r#"say "hi"#), "A quote is missing its closing \".");
+    assert_eq!(error("nick:"), "nick: needs a value, eg. nick:zopieux");
+    assert_eq!(
+        error("before:yesterday"),
+        "yesterday is not a valid date, write it like 2024-01-31."
+    );
+    assert_eq!(
+        error("is:cat"),
+        "is:cat is not supported, use one of: message, action, topic, notice."
+    );
+    assert_eq!(
+        error("has:image"),
+        "has:image is not supported, use one of: link."
+    );
+    assert_eq!(
+        error("-is:action"),
+        "is: cannot be excluded, only nick: can."
+    );
+    assert_eq!(
+        error("after:2024-01-31 before:2024-01-01"),
+        "No day matches these dates."
+    );
+    assert_eq!(
+        error("after:2024-01-01 before:2024-01-02"),
+        "No day matches these dates."
+    );
+    assert_eq!(
+        SearchQuery::parse(&"(a|aa)*".repeat(40), SearchMode::Regex)
+            .unwrap_err()
+            .to_string(),
+        "The pattern is too long, it can have up to 200 characters."
+    );
+    assert!(SearchQuery::parse(&"é".repeat(200), SearchMode::Substring).is_ok());
+    assert!(SearchQuery::parse(&"word ".repeat(200), SearchMode::Text).is_ok());
+}
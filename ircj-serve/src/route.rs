@@ -1,10 +1,15 @@
+use std::collections::{HashMap, HashSet};
+
 use chrono::Datelike;
+use chrono_tz::Tz;
 use itertools::Itertools;
 use maud::Markup;
 use rocket::{
-    http::Status,
+    form::Form,
+    futures::StreamExt,
+    http::{Cookie, CookieJar, Status},
     response::{
-        stream::{Event, EventStream},
+        stream::{Event, EventStream, TextStream},
         Redirect,
     },
     uri, Request, Route, State,
@@ -17,26 +22,86 @@ use tokio::{
 pub use crate::route_static::StaticFiles;
 use ircjournal::{
     model::{Message, ServerChannel},
-    Database, MessageEvent,
+    Database,
 };
 
-use crate::{view, Day};
+use crate::{
+    auth::{check_password, CanLogIn, USER_COOKIE},
+    db::{Cursor, LinesPerDay, SearchLine, SearchResults},
+    export::{Export, ExportFormat, Exporter},
+    query::{SearchMode, SearchQuery},
+    view, Config, Day, DisplayTz, LocalTime, MessageEvent, MessageExt, TextDay, Viewer,
+};
 
 #[get("/")]
-async fn home(db: &State<Database>) -> Option<Markup> {
-    let channels = crate::db::channels(db).await;
-    Some(view::home(&channels))
+async fn home(
+    db: &State<Database>,
+    config: &State<Config>,
+    can_log_in: &State<CanLogIn>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Markup> {
+    let channels = crate::db::channels(db, &viewer).await;
+    let can_log_in = can_log_in.0 && !config.users.is_empty();
+    Some(view::home(
+        &channels,
+        viewer.user.as_deref(),
+        can_log_in,
+        &tz.0,
+    ))
+}
+
+#[derive(FromForm)]
+struct Login<'r> {
+    user: &'r str,
+    password: &'r str,
+}
+
+#[get("/login")]
+fn login_form() -> Markup {
+    view::login(false)
+}
+
+#[post("/login", data = "<login>")]
+async fn login(
+    config: &State<Config>,
+    cookies: &CookieJar<'_>,
+    login: Form<Login<'_>>,
+) -> Result<Redirect, (Status, Markup)> {
+    if !check_password(config, login.user, login.password).await {
+        return Err((Status::Unauthorized, view::login(true)));
+    }
+    cookies.add_private(
+        Cookie::build((USER_COOKIE, login.user.to_string()))
+            .path("/")
+            .http_only(true),
+    );
+    Ok(Redirect::to(uri!(home)))
+}
+
+#[post("/logout")]
+fn logout(cookies: &CookieJar<'_>) -> Redirect {
+    cookies.remove_private(USER_COOKIE);
+    Redirect::to(uri!(home))
 }
 
 #[get("/<sc>")]
-async fn channel_redirect(db: &State<Database>, sc: ServerChannel) -> Redirect {
-    Redirect::temporary(
+async fn channel_redirect(
+    db: &State<Database>,
+    sc: ServerChannel,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Redirect> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    Some(Redirect::temporary(
         if let Some(ts) = ircjournal::db::last_message_ts(db, &sc).await {
-            uri!(channel(&sc, ts.into()))
+            uri!(channel(&sc, Day::at(&ts, &tz.0)))
         } else {
             uri!("/")
         },
-    )
+    ))
 }
 
 #[get("/<sc>/stream")]
@@ -44,97 +109,397 @@ async fn channel_stream(
     sc: ServerChannel,
     db: &State<Database>,
     queue: &State<Sender<MessageEvent>>,
+    viewer: Viewer,
+    tz: DisplayTz,
     mut end: rocket::Shutdown,
 ) -> Option<EventStream![]> {
-    if !crate::db::channel_exists(db, &sc).await {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
         return None;
     }
     let mut rx = queue.subscribe();
     Some(EventStream! {
         loop {
-            let message_html = select! {
+            let event = select! {
                 msg = rx.recv() => match msg {
-                    Ok((for_sc, message_html)) if for_sc == sc => message_html,
+                    Ok((for_sc, event)) if for_sc == sc => event,
                     Err(RecvError::Closed) => break,
                     _ => continue,
                 },
                 _ = &mut end => break,
             };
-            yield Event::data(message_html);
+            let (message, nicks) = &*event;
+            yield Event::data(view::formatted_message(message, nicks, &tz.0));
         }
     })
 }
 
 #[get("/<sc>/<day>")]
-async fn channel(db: &State<Database>, sc: ServerChannel, day: Day) -> Option<Markup> {
-    let (messages, info, active_days) = {
+async fn channel(
+    db: &State<Database>,
+    sc: ServerChannel,
+    day: Day,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Markup> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let tz = &tz.0;
+    let (page, info, active_days) = {
+        let (from, to) = (day.midnight(tz), day.succ().midnight(tz));
         tokio::join!(
-            crate::db::messages_channel_day(db, &sc, &day),
-            crate::db::channel_info(db, &sc, &day),
-            crate::db::channel_month_index(db, &sc, day.0.year(), day.0.month()),
+            // The rest is loaded as the visitor scrolls, see `channel_more`.
+            crate::db::messages_channel_range(db, &sc, &from, Some(&to), None),
+            crate::db::channel_info(db, &sc, &day, tz),
+            crate::db::channel_month_index(db, &sc, day.0.year(), day.0.month(), tz),
         )
     };
-    let truncated = messages.len() == crate::db::HARD_MESSAGE_LIMIT;
-    Some(view::channel(
-        &info?,
-        &day,
-        &messages,
-        &active_days,
-        truncated,
+    Some(view::channel(&info?, &day, &page, &active_days, tz))
+}
+
+/// The messages of a day after or before the message with the given id, as table rows.
+#[get("/<sc>/<day>/more?<after>&<before>")]
+async fn channel_more(
+    db: &State<Database>,
+    sc: ServerChannel,
+    day: Day,
+    after: Option<i32>,
+    before: Option<i32>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Markup> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let tz = &tz.0;
+    let cursor = Cursor::new(after, before)?;
+    let (page, info) = {
+        let (from, to) = (day.midnight(tz), day.succ().midnight(tz));
+        tokio::join!(
+            crate::db::messages_channel_range(db, &sc, &from, Some(&to), Some(cursor)),
+            crate::db::channel_info(db, &sc, &day, tz),
+        )
+    };
+    Some(view::message_rows(&page.records, &info?, tz))
+}
+
+/// Streams the messages of `sc` from `from` to `to` included.
+fn export(
+    db: &Database,
+    sc: ServerChannel,
+    from: Day,
+    to: Day,
+    format: ExportFormat,
+    tz: Tz,
+    attachment: bool,
+) -> Export<TextStream![String]> {
+    let db = db.clone();
+    let (from_, to_) = (from.clone(), to.clone());
+    let sc_ = sc.clone();
+    let stream = TextStream! {
+        let mut exporter = Exporter::new(format, &sc_, &tz);
+        if let Some(header) = exporter.header() {
+            yield header;
+        }
+        let mut messages = crate::db::messages_channel_days(&db, &sc_, &from_, &to_, &tz);
+        // Stops at the first error, there is no way to tell the client past the headers.
+        while let Some(Ok(message)) = messages.next().await {
+            yield exporter.lines(&message);
+        }
+    };
+    Export::new(stream, format, &sc, &from, &to, attachment)
+}
+
+#[get("/<sc>/<day>", rank = 2)]
+async fn channel_text(
+    db: &State<Database>,
+    sc: ServerChannel,
+    day: TextDay,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Export<TextStream![String]>> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let day = day.0;
+    Some(export(
+        db,
+        sc,
+        day.clone(),
+        day,
+        ExportFormat::Plain,
+        tz.0,
+        false,
     ))
 }
 
-#[get("/<sc>/search?<query>&<page>")]
+#[get("/<sc>/export?<from>&<to>&<format>")]
+async fn channel_export(
+    db: &State<Database>,
+    sc: ServerChannel,
+    from: Day,
+    to: Option<Day>,
+    format: Option<ExportFormat>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Export<TextStream![String]>> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let to = to.unwrap_or_else(|| from.clone());
+    let format = format.unwrap_or(ExportFormat::Plain);
+    Some(export(db, sc, from, to, format, tz.0, true))
+}
+
+#[get("/<sc>/range?<from>&<to>&<after>&<before>")]
+#[allow(clippy::too_many_arguments)]
+async fn channel_range(
+    db: &State<Database>,
+    sc: ServerChannel,
+    from: LocalTime,
+    to: Option<LocalTime>,
+    after: Option<i32>,
+    before: Option<i32>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Markup> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let tz = &tz.0;
+    let cursor = Cursor::new(after, before);
+    let (page, info) = {
+        let (from, to) = (from.to_utc(tz), to.map(|to| to.to_utc(tz)));
+        let today = Day::today(tz);
+        tokio::join!(
+            crate::db::messages_channel_range(db, &sc, &from, to.as_ref(), cursor),
+            crate::db::channel_info(db, &sc, &today, tz),
+        )
+    };
+    Some(view::range(&info?, &from, to.as_ref(), &page, tz))
+}
+
+#[get("/<sc>/stats")]
+async fn channel_stats(
+    db: &State<Database>,
+    sc: ServerChannel,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Option<Markup> {
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let tz = &tz.0;
+    let (stats, info) = {
+        let today = Day::today(tz);
+        tokio::join!(
+            crate::db::channel_stats(db, &sc, tz),
+            crate::db::channel_info(db, &sc, &today, tz),
+        )
+    };
+    Some(view::stats(&info?, &stats, tz))
+}
+
+#[get("/<sc>/search?<query>&<mode>&<context>&<after>&<before>")]
+#[allow(clippy::too_many_arguments)]
 async fn channel_search(
     db: &State<Database>,
     sc: ServerChannel,
     query: &str,
-    page: Option<u64>,
+    mode: Option<SearchMode>,
+    context: Option<u8>,
+    after: Option<i32>,
+    before: Option<i32>,
+    viewer: Viewer,
+    tz: DisplayTz,
 ) -> Option<Markup> {
-    let page = page.unwrap_or(1);
-    let (result_page, info) = {
-        let query = query.to_string();
-        let today = Day::today();
+    if !crate::db::channel_visible(db, &sc, &viewer).await {
+        return None;
+    }
+    let tz = &tz.0;
+    let mode = mode.unwrap_or_default();
+    let cursor = Cursor::new(after, before);
+    let ((results, error), info) = {
+        let channels = [sc.to_string()];
+        let today = Day::today(tz);
         tokio::join!(
-            crate::db::channel_search(db, &sc, &query, page as i64),
-            crate::db::channel_info(db, &sc, &today),
+            search_results(db, &channels, query, mode, cursor, tz),
+            crate::db::channel_info(db, &sc, &today, tz),
         )
     };
-    let messages: Vec<_> = result_page
-        .records
+    let context = context.unwrap_or(0);
+    let around = crate::db::search_context(db, &results.page.records, context).await;
+    Some(view::search(
+        &info?,
+        query,
+        mode,
+        context,
+        error.as_deref(),
+        &per_day(search_lines(&results.page.records, &around), tz),
+        &results,
+        tz,
+    ))
+}
+
+/// Runs `query`, or explains why it cannot be parsed.
+async fn search_results(
+    db: &Database,
+    channels: &[String],
+    query: &str,
+    mode: SearchMode,
+    cursor: Option<Cursor>,
+    tz: &Tz,
+) -> (SearchResults, Option<String>) {
+    let results = match SearchQuery::parse(query, mode) {
+        Ok(query) => crate::db::search(db, channels, &query, cursor, tz).await,
+        Err(error) => Err(error),
+    };
+    match results {
+        Ok(results) => (results, None),
+        Err(error) => (SearchResults::default(), Some(error.to_string())),
+    }
+}
+
+/// Search results and the lines `around` them, most recent first. The lines around overlapping
+/// results make a single block.
+fn search_lines<'a>(hits: &'a [Message], around: &'a [(i32, Message)]) -> Vec<SearchLine<'a>> {
+    let key = |m: &Message| (m.channel.clone(), m.timestamp, m.id);
+    // The first and last line around each result.
+    let mut windows: HashMap<i32, _> = hits.iter().map(|m| (m.id, (key(m), key(m)))).collect();
+    for (hit, m) in around {
+        if let Some((first, last)) = windows.get_mut(hit) {
+            let k = key(m);
+            if k < *first {
+                *first = k;
+            } else if k > *last {
+                *last = k;
+            }
+        }
+    }
+    let mut blocks: Vec<(_, _)> = vec![];
+    for (first, last) in windows.into_values().sorted() {
+        match blocks.last_mut() {
+            Some((_, end)) if first <= *end => *end = last.max(end.clone()),
+            _ => blocks.push((first, last)),
+        }
+    }
+    let mut seen = HashSet::new();
+    let mut lines: Vec<SearchLine> = hits
+        .iter()
+        .map(|m| (m, false))
+        .chain(around.iter().map(|(_, m)| (m, true)))
+        .filter(|(m, _)| seen.insert(m.id))
+        .map(|(message, context)| {
+            let k = key(message);
+            SearchLine {
+                message,
+                context,
+                block: blocks
+                    .iter()
+                    .position(|(first, last)| *first <= k && k <= *last)
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+    lines.sort_by_key(|line| std::cmp::Reverse((line.message.timestamp, line.message.id)));
+    lines
+}
+
+/// Groups search results, most recent day first.
+fn per_day<'a>(lines: Vec<SearchLine<'a>>, tz: &Tz) -> Vec<LinesPerDay<'a>> {
+    lines
         .into_iter()
-        .group_by(|msg| msg.timestamp.date_naive())
+        .group_by(|line| Day::at(&line.message.timestamp, tz))
         .into_iter()
         .map(|(day, group)| {
-            (day.into(), {
-                // By now all messages are still in descending chronological order.
+            (day, {
+                // By now all lines are still in descending chronological order.
                 // For a given day to make sense, reverse order, within each day.
-                let mut messages: Vec<Message> = group.collect();
-                messages.reverse();
-                messages
+                let mut lines: Vec<SearchLine> = group.collect();
+                lines.reverse();
+                lines
             })
         })
+        .collect()
+}
+
+/// Searches all visible channels, or those of `server`, or only the selected `channel`s.
+#[get("/search?<query>&<mode>&<context>&<after>&<before>&<server>&<channel>")]
+#[allow(clippy::too_many_arguments)]
+async fn search(
+    db: &State<Database>,
+    query: &str,
+    mode: Option<SearchMode>,
+    context: Option<u8>,
+    after: Option<i32>,
+    before: Option<i32>,
+    server: Option<&str>,
+    channel: Vec<String>,
+    viewer: Viewer,
+    tz: DisplayTz,
+) -> Markup {
+    let tz = &tz.0;
+    let server = server.filter(|server| !server.is_empty());
+    let channels = crate::db::channels(db, &viewer).await;
+    let scope: Vec<String> = channels
+        .iter()
+        .filter(|sc| server.is_none_or(|server| sc.server == server))
+        .map(|sc| sc.to_string())
+        .filter(|name| channel.is_empty() || channel.contains(name))
         .collect();
-    Some(view::search(
-        &info?,
+    let mode = mode.unwrap_or_default();
+    let cursor = Cursor::new(after, before);
+    let (results, error) = search_results(db, &scope, query, mode, cursor, tz).await;
+    let context = context.unwrap_or(0);
+    let around = crate::db::search_context(db, &results.page.records, context).await;
+    // Keeps channels in the order of their most recent result.
+    let mut per_channel: Vec<(ServerChannel, Vec<SearchLine>)> = vec![];
+    for line in search_lines(&results.page.records, &around) {
+        let sc = line.message.sc();
+        match per_channel.iter_mut().find(|(other, _)| *other == sc) {
+            Some((_, lines)) => lines.push(line),
+            None => per_channel.push((sc, vec![line])),
+        }
+    }
+    let grouped: Vec<_> = per_channel
+        .into_iter()
+        .map(|(sc, lines)| (sc, per_day(lines, tz)))
+        .collect();
+    view::global_search(
+        &channels,
         query,
-        &messages,
-        page,
-        result_page.page_count,
-        result_page.total,
-    ))
+        mode,
+        context,
+        error.as_deref(),
+        server,
+        &channel,
+        &grouped,
+        &results,
+        tz,
+    )
 }
 
 pub fn routes() -> Vec<Route> {
     routes![
         home,
+        search,
         channel_redirect,
         channel_stream,
         channel_search,
+        channel_stats,
+        channel_range,
+        channel_export,
         channel,
+        channel_more,
+        channel_text,
     ]
 }
 
+/// Only mounted when logging in is possible, see [`crate::auth::fairing`].
+pub fn login_routes() -> Vec<Route> {
+    routes![login_form, login, logout]
+}
+
 #[catch(default)]
 fn catch_default(status: Status, _: &Request) -> String {
     format!("{}", status)
@@ -143,3 +508,43 @@ fn catch_default(status: Status, _: &Request) -> String {
 pub fn catchers() -> Vec<rocket::Catcher> {
     rocket::catchers![catch_default]
 }
+
+#[test]
+fn test_search_lines() {
+    let message = |id: i32| Message {
+        id,
+        channel: Some("libera/#chan".to_string()),
+        nick: Some("zopieux".to_string()),
+        line: Some(format!("line {}", id)),
+        opcode: None,
+        oper_nick: None,
+        payload: None,
+        timestamp: chrono::DateTime::from_timestamp(60 * id as i64, 0).unwrap(),
+        occurrence: 0,
+    };
+    // Most recent first, with the line before and after.
+    let hits = vec![message(11), message(5), message(3)];
+    let around: Vec<_> = [(11, 10), (11, 12), (5, 4), (5, 6), (3, 2), (3, 4)]
+        .iter()
+        .map(|&(hit, id)| (hit, message(id)))
+        .collect();
+    let lines = search_lines(&hits, &around);
+    let summary: Vec<_> = lines
+        .iter()
+        .map(|line| (line.message.id, line.context, line.block))
+        .collect();
+    // The lines around 3 and 5 overlap, and are merged.
+    assert_eq!(
+        summary,
+        vec![
+            (12, true, 1),
+            (11, false, 1),
+            (10, true, 1),
+            (6, true, 0),
+            (5, false, 0),
+            (4, true, 0),
+            (3, false, 0),
+            (2, true, 0),
+        ]
+    );
+}
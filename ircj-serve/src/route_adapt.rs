@@ -1,11 +1,22 @@
 use std::fmt;
 
 use rocket::{
-    http::uri::fmt::{Formatter, FromUriParam, Path, UriDisplay},
-    request::FromParam,
+    form::{self, FromFormField, ValueField},
+    http::{
+        uri::fmt::{Formatter, FromUriParam, Path, Query, UriDisplay},
+        Cookie,
+    },
+    request::{FromParam, FromRequest, Outcome},
+    Request,
 };
 
-use crate::Day;
+use crate::{
+    auth::{CanLogIn, USER_COOKIE},
+    query::SearchMode,
+    Config, Day, DisplayTz, LocalTime, TextDay, Viewer,
+};
+
+const TZ_PARAM: &str = "tz";
 
 impl UriDisplay<Path> for Day {
     fn fmt(&self, f: &mut Formatter<'_, Path>) -> fmt::Result {
@@ -29,3 +40,123 @@ impl FromUriParam<Path, Day> for Day {
         param
     }
 }
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for Day {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Ok(Day::from_param(field.value).map_err(|_| form::Error::validation("invalid date"))?)
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for LocalTime {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        // Browsers omit the seconds when they are zero.
+        let parse = |format| chrono::NaiveDateTime::parse_from_str(field.value, format);
+        let naive = parse("%Y-%m-%dT%H:%M")
+            .or_else(|_| parse("%Y-%m-%dT%H:%M:%S"))
+            .map_err(|_| form::Error::validation("invalid date and time"))?;
+        Ok(LocalTime(naive))
+    }
+}
+
+impl UriDisplay<Query> for LocalTime {
+    fn fmt(&self, f: &mut Formatter<'_, Query>) -> fmt::Result {
+        f.write_value(self.iso())
+    }
+}
+
+impl FromUriParam<Query, LocalTime> for LocalTime {
+    type Target = LocalTime;
+
+    fn from_uri_param(param: LocalTime) -> Self::Target {
+        param
+    }
+}
+
+impl UriDisplay<Query> for SearchMode {
+    fn fmt(&self, f: &mut Formatter<'_, Query>) -> fmt::Result {
+        f.write_value(self.name())
+    }
+}
+
+impl FromUriParam<Query, SearchMode> for SearchMode {
+    type Target = SearchMode;
+
+    fn from_uri_param(param: SearchMode) -> Self::Target {
+        param
+    }
+}
+
+impl UriDisplay<Path> for TextDay {
+    fn fmt(&self, f: &mut Formatter<'_, Path>) -> fmt::Result {
+        f.write_raw(format!("{}.txt", self.0.ymd()))
+    }
+}
+
+impl<'r> FromParam<'r> for TextDay {
+    type Error = Option<chrono::ParseError>;
+
+    fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        let day = param.strip_suffix(".txt").ok_or(None)?;
+        Ok(TextDay(Day::from_param(day)?))
+    }
+}
+
+impl FromUriParam<Path, TextDay> for TextDay {
+    type Target = TextDay;
+
+    fn from_uri_param(param: TextDay) -> Self::Target {
+        param
+    }
+}
+
+/// Reads the time zone from the `tz` query parameter, remembering it in a cookie, or from that
+/// cookie. Invalid zones are ignored.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DisplayTz {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let parse = |name: &str| name.parse::<chrono_tz::Tz>().ok();
+        let from_query = request
+            .query_value::<&str>(TZ_PARAM)
+            .and_then(|name| parse(name.ok()?));
+        if let Some(tz) = from_query {
+            request
+                .cookies()
+                .add(Cookie::build((TZ_PARAM, tz.name())).path("/").permanent());
+            return Outcome::Success(DisplayTz(tz));
+        }
+        let from_cookie = request
+            .cookies()
+            .get(TZ_PARAM)
+            .and_then(|cookie| parse(cookie.value()));
+        Outcome::Success(DisplayTz(from_cookie.unwrap_or(chrono_tz::UTC)))
+    }
+}
+
+/// Identifies the logged in user, from the login cookie or else from the trusted reverse-proxy
+/// header.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Viewer {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = request.rocket().state::<Config>().unwrap(); // attached on launch.
+        let can_log_in = request.rocket().state::<CanLogIn>().unwrap(); // attached on launch.
+        let from_cookie = can_log_in
+            .0
+            .then(|| request.cookies().get_private(USER_COOKIE))
+            .flatten()
+            .map(|cookie| cookie.value().to_string())
+            // The user may have been removed since.
+            .filter(|user| config.users.contains_key(user));
+        let from_header = || {
+            let header = config.auth_header.as_deref()?;
+            let user = request.headers().get_one(header)?;
+            (!user.is_empty()).then(|| user.to_string())
+        };
+        Outcome::Success(Viewer::new(config, from_cookie.or_else(from_header)))
+    }
+}
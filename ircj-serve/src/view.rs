@@ -1,4 +1,5 @@
 use chrono::{Datelike, NaiveDate};
+use chrono_tz::Tz;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use maud::{html, Markup, PreEscaped, DOCTYPE};
@@ -6,12 +7,24 @@ use regex::Regex;
 use rocket::uri;
 use std::{collections::HashSet, str::FromStr};
 
-use ircjournal::model::{Message, ServerChannel};
+use ircjournal::model::{Datetime, Message, ServerChannel};
 
-use crate::{db::MessagesPerDay, route, ChannelInfo, Day, MessageExt, Nicks};
+use crate::{
+    db::{
+        ChannelStats, Cursor, KeysetPage, LinesPerDay, SearchLine, SearchResults,
+        SEARCH_COUNT_LIMIT, STATS_NICK_MONTHS,
+    },
+    query::SearchMode,
+    route, ChannelInfo, Day, LocalTime, MessageExt, Nicks, TextDay,
+};
 
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 const LINK_TRUNCATE_LENGTH: usize = 40;
+/// Shades of the heatmap, besides none.
+const HEAT_LEVELS: i64 = 8;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+/// Lines to show around search results, to pick from.
+const SEARCH_CONTEXT_CHOICES: [u8; 4] = [0, 1, 3, 5];
 
 enum LinkType {
     Absolute,
@@ -26,18 +39,8 @@ fn channel_link(sc: &ServerChannel, day: &Day, content: Markup) -> Markup {
     html! { a href=(uri!(route::channel(sc, day.clone()))) { (content) } }
 }
 
-fn message_link(m: &Message, content: Markup) -> Markup {
-    html! { a href={(uri!(route::channel(&m.sc(), m.timestamp.into()))) "#" (m.id_str())} { (content) } }
-}
-
-macro_rules! format_some {
-    ($s: expr, $fmt: literal) => {
-        if let Some(ss) = $s {
-            format!($fmt, ss)
-        } else {
-            "".to_string()
-        }
-    };
+fn message_link(m: &Message, tz: &Tz, content: Markup) -> Markup {
+    html! { a href={(uri!(route::channel(&m.sc(), Day::at(&m.timestamp, tz)))) "#" (m.id_str())} { (content) } }
 }
 
 fn base<M, A, C>(
@@ -79,7 +82,12 @@ where
     }
 }
 
-pub(crate) fn home(channels: &[ServerChannel]) -> Markup {
+pub(crate) fn home(
+    channels: &[ServerChannel],
+    user: Option<&str>,
+    can_log_in: bool,
+    tz: &Tz,
+) -> Markup {
     let channel_tree = &channels.iter().group_by(|sc| &sc.server);
     base(
         "Channel list",
@@ -105,12 +113,46 @@ pub(crate) fn home(channels: &[ServerChannel]) -> Markup {
             }
         },
         html! {
+            (global_search_form(channels, "", SearchMode::Text, 0, None, &[]))
             p {
                 "This is "
                 a href="https://github.com/zopieux/ircjournal" rel="nofollow" { "ircjournal" }
                 " v" (VERSION.unwrap_or("?")) ", brought to you by "
                 a href="https://github.com/zopieux" { "zopieux" } "."
             }
+            @if let Some(user) = user {
+                @if can_log_in {
+                    form.logout action=(uri!(route::logout)) method="post" {
+                        "Logged in as " strong { (user) } ". "
+                        button type="submit" { "Log out" }
+                    }
+                } @else {
+                    p { "Logged in as " strong { (user) } "." }
+                }
+            } @else if can_log_in {
+                p { a href=(uri!(route::login_form)) { "Log in" } " to see more channels." }
+            }
+            (timezone_form(tz))
+        },
+        html! {},
+    )
+}
+
+pub(crate) fn login(failed: bool) -> Markup {
+    base(
+        "Log in",
+        html! {
+            (home_link())
+        },
+        html! {
+            form.login action=(uri!(route::login)) method="post" {
+                @if failed { div.warning { "Wrong user or password." } }
+                label for="user" { "User" }
+                input#user type="text" name="user" autocomplete="username" required autofocus;
+                label for="password" { "Password" }
+                input#password type="password" name="password" autocomplete="current-password" required;
+                button type="submit" { "Log in" }
+            }
         },
         html! {},
     )
@@ -119,12 +161,13 @@ pub(crate) fn home(channels: &[ServerChannel]) -> Markup {
 pub(crate) fn channel(
     info: &ChannelInfo,
     day: &Day,
-    messages: &[Message],
+    page: &KeysetPage<Message>,
     active_days: &HashSet<u32>,
-    truncated: bool,
+    tz: &Tz,
 ) -> Markup {
     let sc = &info.sc;
-    let cal = render_calendar(day, info, active_days);
+    let messages = &page.records;
+    let cal = render_calendar(day, info, active_days, tz);
 
     let date_sel = |from, to, jump, jump_tip| {
         let _link_date = |day: &Day| channel_link(sc, day, html! { (day.ymd()) });
@@ -150,44 +193,33 @@ pub(crate) fn channel(
         html! {
             (home_link())
             (cal)
-            (search_form(sc, ""))
-            label for="show-join-part" title="If checked, join, part, quit and nick messages are shown." {
-                input#show-join-part name="show-join-part" type="checkbox" checked;
-                "Show join / leave"
+            (search_form(sc, "", SearchMode::Text, 0))
+            (range_form(sc, &day.start(), Some(&day.succ().start())))
+            (message_controls())
+            p.export {
+                a href=(uri!(route::channel_text(sc, TextDay(day.clone())))) title="This day as a plain text log." { "Download as text" }
             }
-            div.check-group {
-                label for="live" title="Show new messages, as they are logged live." {
-                    input#live name="live" type="checkbox" checked;
-                    "Live update"
-                }
-                label for="auto-scroll" title="Automatically scroll the new messages remain visible." {
-                    input#auto-scroll name="auto-scroll" type="checkbox" checked;
-                    "Auto-scroll"
-                }
-            }
-            form.search {
-                input#filter type="search" placeholder="Search this day";
-            }
-            (clear_selection_button())
+            p { a href=(uri!(route::channel_stats(sc))) title="Who talks, when, and the links they post." { "Statistics" } }
+            (timezone_form(tz))
         },
         html! {
             (date_sel("", "bottom", "\u{22ce}", "Jump to the bottom"))
             @if let Some(topic) = info.topic.as_ref() {
                 blockquote.last-topic {
                     (format_content(some_or_empty(&topic.payload), &HashSet::new()))
-                    cite { "Set by " (format_nick(topic.nick.as_deref().unwrap())) " on " (message_link(topic, html!{ (topic.timestamp.format("%Y-%m-%d at %H:%M")) })) }
+                    cite { "Set by " (format_nick(topic.nick.as_deref().unwrap())) " on " (message_link(topic, tz, html!{ (topic.timestamp.with_timezone(tz).format("%Y-%m-%d at %H:%M")) })) }
                 }
             }
-            @let maybe_stream = day.is_today_or_future().then(|| uri!(route::channel_stream(sc)));
-            table.messages data-stream=[maybe_stream] {
+            @let maybe_stream = day.is_today_or_future(tz).then(|| uri!(route::channel_stream(sc)));
+            @let maybe_more = page.has_later.then(|| uri!(route::channel_more(sc, day.clone(), None as Option<i32>, None as Option<i32>)));
+            table.messages data-stream=[maybe_stream] data-more=[maybe_more] {
                 tbody {
-                    @for msg in messages { (message(msg, sc, &info.nicks, LinkType::Relative)) }
+                    @for msg in messages { (message(msg, sc, &info.nicks, LinkType::Relative, tz)) }
                 }
             }
             @if messages.is_empty() {
                 p.empty { "No messages for " (day.ymd()) "." }
             }
-            @if truncated { div.warning { "Only displaying the first " (messages.len()) " lines to prevent browser slowness." } }
             (date_sel("bottom", "", "\u{22cf}", "Jump to the top"))
             div#bottom {}
         },
@@ -198,47 +230,216 @@ pub(crate) fn channel(
     )
 }
 
-pub(crate) fn search(
+pub(crate) fn range(
     info: &ChannelInfo,
-    query: &str,
-    messages: &[MessagesPerDay],
-    page: u64,
-    page_count: i64,
-    result_count: i64,
+    from: &LocalTime,
+    to: Option<&LocalTime>,
+    page: &KeysetPage<Message>,
+    tz: &Tz,
 ) -> Markup {
     let sc = &info.sc;
-    let pages: Vec<Markup> = (1..=page_count)
-        .map(|p| {
-            html! {
-                @if p as u64 == page {
-                    strong { (p) }
-                } @else {
-                    a href=(uri!(route::channel_search(sc, query, Some(p as u64)))) title=(format!("Page {}", p)) { (p) }
+    let messages = &page.records;
+    let per_day = messages.iter().group_by(|msg| Day::at(&msg.timestamp, tz));
+    base(
+        &sc.to_string(),
+        html! {
+            (home_link())
+            a href=(uri!(route::channel_redirect(sc))) { "Back to channel" }
+            (range_form(sc, from, to))
+            (message_controls())
+            (timezone_form(tz))
+        },
+        html! {
+            @if let (true, Some(first)) = (page.has_earlier, messages.first()) {
+                p.load { a href=(uri!(route::channel_range(sc, *from, to.copied(), None as Option<i32>, Some(first.id)))) { "Load earlier" } }
+            }
+            table.messages {
+                @for (day, group) in &per_day {
+                    tbody.search-date { tr { td colspan="3" { (channel_link(sc, &day, html! { (day.ymd()) })) } } }
+                    tbody {
+                        @for msg in group { (message(msg, sc, &info.nicks, LinkType::Relative, tz)) }
+                    }
                 }
             }
-        })
-        .collect();
-    let pages = html! { @if pages.len() > 1 { div.pages { @for p in pages { (p) } } } };
+            @if messages.is_empty() {
+                p.empty { "No messages from " (from.iso()) @if let Some(to) = to { " to " (to.iso()) } "." }
+            }
+            @if let (true, Some(last)) = (page.has_later, messages.last()) {
+                p.load { a href=(uri!(route::channel_range(sc, *from, to.copied(), Some(last.id), None as Option<i32>))) { "Load later" } }
+            }
+            div#bottom {}
+        },
+        html! {
+            link rel="preload" href="/static/js/ircjournal.js" as="script";
+            script type="text/javascript" src="/static/js/ircjournal.js" defer async {}
+        },
+    )
+}
+
+pub(crate) fn stats(info: &ChannelInfo, stats: &ChannelStats, tz: &Tz) -> Markup {
+    let sc = &info.sc;
+    let total: i64 = stats.months.iter().map(|m| m.messages).sum();
+    let links: i64 = stats.months.iter().map(|m| m.links).sum();
+    let busiest_hour = stats.heatmap.iter().flatten().copied().max().unwrap_or(0);
+    let busiest_month = stats.months.iter().map(|m| m.messages).max().unwrap_or(0);
+    let date = |ts: &Datetime| {
+        let day = Day::at(ts, tz);
+        channel_link(sc, &day, html! { (day.ymd()) })
+    };
     base(
         &sc.to_string(),
         html! {
             (home_link())
             a href=(uri!(route::channel_redirect(sc))) { "Back to channel" }
-            (search_form(sc, query))
+            (timezone_form(tz))
         },
         html! {
-            div {
-                @if result_count == 0 {
-                    "No message found."
-                } @else {
-                    "Found " strong { (result_count) } " lines. "
-                    (pages)
+            p {
+                "Logged from " (channel_link(sc, &info.first_day, html! { (info.first_day.ymd()) }))
+                " to " (channel_link(sc, &info.last_day, html! { (info.last_day.ymd()) })) ": "
+                (counted(total, "message")) " and " (counted(links, "link")) "."
+            }
+            section.stats {
+                h2 { "Messages by day and hour" }
+                table.heatmap {
+                    tr {
+                        th {}
+                        @for hour in 0..24 { th { (format!("{:02}", hour)) } }
+                    }
+                    @for (weekday, hours) in WEEKDAYS.iter().zip(stats.heatmap.iter()) {
+                        tr {
+                            th { (weekday) }
+                            @for count in hours {
+                                td class={"heat-" (heat(*count, busiest_hour))} title=(thousands(*count)) {}
+                            }
+                        }
+                    }
                 }
             }
+            section.stats {
+                h2 { "Top talkers" }
+                table {
+                    tr {
+                        th { "Nick" }
+                        th { "Messages" }
+                        th { "First seen" }
+                        th { "Last seen" }
+                        th title={"Messages in the last " (STATS_NICK_MONTHS) " months of the channel."} { "Activity" }
+                    }
+                    @for nick in &stats.nicks {
+                        @let busiest = nick.recent.iter().copied().max().unwrap_or(0);
+                        tr {
+                            td { (format_nick(&nick.nick)) }
+                            td { (thousands(nick.messages)) }
+                            td { (date(&nick.first_seen)) }
+                            td { (date(&nick.last_seen)) }
+                            td.recent {
+                                @for count in &nick.recent {
+                                    span class={"heat-" (heat(*count, busiest))} title=(thousands(*count)) {}
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            section.stats {
+                h2 { "Busiest days" }
+                table {
+                    @for (day, count) in &stats.busiest_days {
+                        tr {
+                            td { (channel_link(sc, day, html! { (day.ymd()) })) }
+                            td { (thousands(*count)) }
+                        }
+                    }
+                }
+            }
+            section.stats {
+                h2 { "Per month" }
+                table {
+                    tr {
+                        th { "Month" }
+                        th colspan="2" { "Messages" }
+                        th { "Joins" }
+                        th { "Parts" }
+                        th { "Links" }
+                    }
+                    @for month in stats.months.iter().rev() {
+                        tr {
+                            td { (month.month.format("%Y-%m")) }
+                            td { (thousands(month.messages)) }
+                            td.bar { span style={"width: " (percent(month.messages, busiest_month)) "%"} {} }
+                            td { (thousands(month.joins)) }
+                            td { (thousands(month.parts)) }
+                            td { (thousands(month.links)) }
+                        }
+                    }
+                }
+            }
+            section.stats {
+                h2 { "Recent links" }
+                table.messages {
+                    @for msg in &stats.links { (message(msg, sc, &info.nicks, LinkType::Absolute, tz)) }
+                }
+            }
+        },
+        html! {},
+    )
+}
+
+/// The shade of `count` in a heatmap up to `max`, 0 for none.
+fn heat(count: i64, max: i64) -> i64 {
+    if count <= 0 || max <= 0 {
+        0
+    } else {
+        1 + (count - 1) * HEAT_LEVELS / max
+    }
+}
+
+fn percent(count: i64, max: i64) -> i64 {
+    if max <= 0 {
+        0
+    } else {
+        count * 100 / max
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn search(
+    info: &ChannelInfo,
+    query: &str,
+    mode: SearchMode,
+    context: u8,
+    error: Option<&str>,
+    lines: &[LinesPerDay],
+    results: &SearchResults,
+    tz: &Tz,
+) -> Markup {
+    let sc = &info.sc;
+    let pages = search_navigation(&results.page, |cursor| {
+        let (after, before) = cursor.params();
+        uri!(route::channel_search(
+            sc,
+            query,
+            Some(mode),
+            Some(context),
+            after,
+            before
+        ))
+        .to_string()
+    });
+    base(
+        &sc.to_string(),
+        html! {
+            (home_link())
+            a href=(uri!(route::channel_redirect(sc))) { "Back to channel" }
+            (search_form(sc, query, mode, context))
+        },
+        html! {
+            (search_summary(error, results, &pages))
             table.messages {
-                @for per_day in messages {
-                    tbody.search-date { tr { td colspan="3" { (per_day.0.ymd()) } } }
-                    @for msg in &per_day.1 { (message(msg, sc, &info.nicks, LinkType::Absolute)) }
+                @for (day, lines) in lines {
+                    tbody.search-date { tr { td colspan="3" { (day.ymd()) } } }
+                    (search_lines(lines, context, sc, &info.nicks, tz))
                 }
             }
             (pages)
@@ -247,12 +448,146 @@ pub(crate) fn search(
     )
 }
 
-pub(crate) fn formatted_message(m: &Message, nicks: &Nicks) -> String {
+/// More messages of a page, to be appended by the script.
+pub(crate) fn message_rows(messages: &[Message], info: &ChannelInfo, tz: &Tz) -> Markup {
+    html! {
+        @for msg in messages { (message(msg, &info.sc, &info.nicks, LinkType::Relative, tz)) }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn global_search(
+    channels: &[ServerChannel],
+    query: &str,
+    mode: SearchMode,
+    context: u8,
+    error: Option<&str>,
+    server: Option<&str>,
+    selected: &[String],
+    grouped: &[(ServerChannel, Vec<LinesPerDay>)],
+    results: &SearchResults,
+    tz: &Tz,
+) -> Markup {
+    let pages = search_navigation(&results.page, |cursor| {
+        let (after, before) = cursor.params();
+        uri!(route::search(
+            query,
+            Some(mode),
+            Some(context),
+            after,
+            before,
+            server,
+            selected.to_vec()
+        ))
+        .to_string()
+    });
+    let no_nicks = Nicks::new();
+    base(
+        "Search",
+        html! {
+            (home_link())
+            (global_search_form(channels, query, mode, context, server, selected))
+        },
+        html! {
+            (search_summary(error, results, &pages))
+            @for (sc, per_day) in grouped {
+                section.search-channel {
+                    h2 { a href=(uri!(route::channel_redirect(sc))) { (sc) } }
+                    table.messages {
+                        @for (day, lines) in per_day {
+                            tbody.search-date { tr { td colspan="3" { (day.ymd()) } } }
+                            (search_lines(lines, context, sc, &no_nicks, tz))
+                        }
+                    }
+                }
+            }
+            (pages)
+        },
+        html! {},
+    )
+}
+
+/// Lines of a day of search results, context lines dimmed. With `context`, a gap separates blocks
+/// of lines that do not follow each other.
+fn search_lines(
+    lines: &[SearchLine],
+    context: u8,
+    sc: &ServerChannel,
+    nicks: &Nicks,
+    tz: &Tz,
+) -> Markup {
+    html! {
+        @for (i, line) in lines.iter().enumerate() {
+            @if context > 0 && i > 0 && line.block != lines[i - 1].block {
+                tr.gap { td colspan="3" { "…" } }
+            }
+            (message_row(line.message, sc, nicks, LinkType::Absolute, line.context, tz))
+        }
+    }
+}
+
+/// How many lines were found, or why the query is wrong.
+fn search_summary(error: Option<&str>, results: &SearchResults, pages: &Markup) -> Markup {
+    html! {
+        @if let Some(error) = error {
+            div.warning { (error) }
+        } @else {
+            div {
+                @if results.total == 0 {
+                    "No message found."
+                } @else if results.total_capped() {
+                    "Found more than " strong { (thousands(SEARCH_COUNT_LIMIT)) } " lines. "
+                    (pages)
+                } @else {
+                    "Found " strong { (thousands(results.total)) } " lines. "
+                    (pages)
+                }
+            }
+        }
+    }
+}
+
+/// Links to newer and older results, `link` being the URL of the page after or before a result.
+fn search_navigation(page: &KeysetPage<Message>, link: impl Fn(Cursor) -> String) -> Markup {
+    html! {
+        @if page.has_later || page.has_earlier {
+            div.pages {
+                @if let (true, Some(first)) = (page.has_later, page.records.first()) {
+                    a href=(link(Cursor::After(first.id))) { "Newer results" }
+                }
+                @if let (true, Some(last)) = (page.has_earlier, page.records.last()) {
+                    a href=(link(Cursor::Before(last.id))) { "Older results" }
+                }
+            }
+        }
+    }
+}
+
+/// Eg. 1 link, 10,000 links.
+fn counted(n: i64, noun: &str) -> Markup {
+    html! { strong { (thousands(n)) } " " (noun) @if n != 1 { "s" } }
+}
+
+/// Eg. 10,000.
+fn thousands(n: i64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+pub(crate) fn formatted_message(m: &Message, nicks: &Nicks, tz: &Tz) -> String {
     message(
         m,
         &ServerChannel::from_str(m.channel.as_ref().unwrap()).unwrap(),
         nicks,
         LinkType::Relative,
+        tz,
     )
     .into_string()
 }
@@ -265,6 +600,65 @@ fn clear_selection_button() -> Markup {
     html! { button#clear-selection disabled type="button" title="Un-select all selected messages." { "Clear selection" } }
 }
 
+/// Filtering, selection and live update controls of a page of messages, used by the script.
+fn message_controls() -> Markup {
+    html! {
+        div.check-group {
+            label for="show-join-part" title="If checked, join, part, quit and nick messages are shown." {
+                input#show-join-part name="show-join-part" type="checkbox" checked;
+                "Show join / leave"
+            }
+            label for="show-notice" title="If checked, notices are shown." {
+                input#show-notice name="show-notice" type="checkbox" checked;
+                "Show notices"
+            }
+            label for="show-mode" title="If checked, channel and user mode changes are shown." {
+                input#show-mode name="show-mode" type="checkbox" checked;
+                "Show mode changes"
+            }
+            label for="show-ctcp" title="If checked, CTCP requests, such as VERSION, are shown." {
+                input#show-ctcp name="show-ctcp" type="checkbox";
+                "Show CTCP"
+            }
+        }
+        div.check-group {
+            label for="live" title="Show new messages, as they are logged live." {
+                input#live name="live" type="checkbox" checked;
+                "Live update"
+            }
+            label for="auto-scroll" title="Automatically scroll the new messages remain visible." {
+                input#auto-scroll name="auto-scroll" type="checkbox" checked;
+                "Auto-scroll"
+            }
+        }
+        form.search {
+            input#filter type="search" placeholder="Search this page";
+        }
+        (clear_selection_button())
+    }
+}
+
+fn range_form(sc: &ServerChannel, from: &LocalTime, to: Option<&LocalTime>) -> Markup {
+    html! {
+        form.range action=(uri!(route::channel_range(sc, *from, None as Option<LocalTime>, None as Option<i32>, None as Option<i32>))) method="get" {
+            label for="from" title="Read messages across days, eg. a meeting that spans midnight." { "From" }
+            input#from type="datetime-local" name="from" value=(from.iso()) required;
+            label for="to" { "To" }
+            input#to type="datetime-local" name="to" value=[to.map(LocalTime::iso)];
+            button type="submit" { "Show" }
+        }
+    }
+}
+
+fn timezone_form(tz: &Tz) -> Markup {
+    html! {
+        form.timezone method="get" {
+            label for="tz" title="Dates and times are displayed in this time zone, eg. Europe/Paris." { "Time zone" }
+            input#tz type="text" name="tz" value=(tz.name());
+        }
+    }
+}
+
 fn highlight(line: &str) -> Markup {
     if !line.contains('\u{e000}') {
         // Early exit.
@@ -289,14 +683,74 @@ fn highlight(line: &str) -> Markup {
     html! { @for s in out { (s) } }
 }
 
-fn clean(line: &str) -> String {
+pub(crate) fn clean(line: &str) -> String {
     line.replace(['\u{e000}', '\u{e001}'], "")
 }
 
-fn search_form(sc: &ServerChannel, query: &str) -> Markup {
+fn search_form(sc: &ServerChannel, query: &str, mode: SearchMode, context: u8) -> Markup {
     html! {
-        form.search action=(uri!(route::channel_search(sc, "", None as Option<u64>))) method="get" {
+        form.search action=(uri!(route::channel_search(sc, "", None as Option<SearchMode>, None as Option<u8>, None as Option<i32>, None as Option<i32>))) method="get" {
             input type="search" name="query" value=(query) placeholder="Search this channel";
+            (search_mode_select(mode))
+            (search_context_select(context))
+        }
+    }
+}
+
+fn search_mode_select(mode: SearchMode) -> Markup {
+    html! {
+        select name="mode" title="Words also find other forms of a word. Substrings and regular expressions ignore case." {
+            @for m in SearchMode::ALL {
+                option value=(m.name()) selected[m == mode] { (m.label()) }
+            }
+        }
+    }
+}
+
+/// How many lines to show around each result.
+fn search_context_select(context: u8) -> Markup {
+    html! {
+        select name="context" title="Show the lines before and after each result." {
+            @for lines in SEARCH_CONTEXT_CHOICES {
+                option value=(lines) selected[lines == context] {
+                    @match lines {
+                        0 => "No context",
+                        1 => "±1 line",
+                        _ => { "±" (lines) " lines" },
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn global_search_form(
+    channels: &[ServerChannel],
+    query: &str,
+    mode: SearchMode,
+    context: u8,
+    server: Option<&str>,
+    selected: &[String],
+) -> Markup {
+    let servers = channels.iter().map(|sc| &sc.server).dedup();
+    html! {
+        form.search.global action=(uri!(route::search("", None as Option<SearchMode>, None as Option<u8>, None as Option<i32>, None as Option<i32>, None as Option<&str>, Vec::<String>::new()))) method="get" {
+            input type="search" name="query" value=(query) placeholder="Search all channels";
+            (search_mode_select(mode))
+            (search_context_select(context))
+            select name="server" title="Only search the channels of this server." {
+                option value="" { "All servers" }
+                @for s in servers {
+                    option value=(s) selected[server == Some(s.as_str())] { (s) }
+                }
+            }
+            select name="channel" multiple title="Only search these channels. Select none to search them all." {
+                @for sc in channels {
+                    @let name = sc.to_string();
+                    option value=(name) selected[selected.contains(&name)] { (sc) }
+                }
+            }
+            button type="submit" { "Search" }
         }
     }
 }
@@ -349,28 +803,53 @@ fn format_content(content: &str, nicks: &Nicks) -> Markup {
     html! { @for m in markup { (m) } }
 }
 
+fn format_reason(reason: &Option<String>) -> String {
+    match reason.as_deref() {
+        None | Some("") => "".to_string(),
+        Some(reason) => format!(" ({})", reason),
+    }
+}
+
 fn format_message(m: &Message, nicks: &Nicks) -> Markup {
     html! {
         @match m.opcode.as_deref() {
-            None | Some("me") => (format_content(some_or_empty(&m.line), nicks)),
-            Some("joined") => (format_nick(m.nick.as_ref().unwrap())) " has joined",
-            Some("left") => (format_nick(m.nick.as_ref().unwrap())) " has left" (format_some!(&m.payload, " ({})")),
-            Some("quit") => (format_nick(m.nick.as_ref().unwrap())) " has quit" (format_some!(&m.payload, " ({})")),
-            Some("topic") => (format_nick(m.nick.as_ref().unwrap())) " changed the topic to " span."new-topic" { (some_or_empty(&m.payload)) },
-            Some("nick") => (format_nick(m.nick.as_ref().unwrap())) " is now known as " (format_nick(m.nick.as_ref().unwrap())),
-            _ => "UNIMPLEMENTED: " (some_or_empty(&m.opcode)),
+            None => (format_content(some_or_empty(&m.line), nicks)),
+            Some("me") => (format_nick(some_or_empty(&m.nick))) " " (format_content(some_or_empty(&m.line), nicks)),
+            Some("joined") => (format_nick(some_or_empty(&m.nick))) " has joined",
+            Some("left") => (format_nick(some_or_empty(&m.nick))) " has left" (format_reason(&m.payload)),
+            Some("quit") => (format_nick(some_or_empty(&m.nick))) " has quit" (format_reason(&m.payload)),
+            Some("kicked") => (format_nick(some_or_empty(&m.oper_nick))) " has kicked " (format_nick(some_or_empty(&m.nick))) (format_reason(&m.payload)),
+            Some("topic") => (format_nick(some_or_empty(&m.nick))) " changed the topic to " span."new-topic" { (some_or_empty(&m.payload)) },
+            Some("nick") => (format_nick(some_or_empty(&m.nick))) " is now known as " (format_nick(some_or_empty(&m.payload))),
+            Some("notice") => "Notice from " (format_nick(some_or_empty(&m.nick))) ": " (format_content(some_or_empty(&m.line), nicks)),
+            Some("mode") => (format_nick(some_or_empty(&m.nick))) " sets mode " span.mode { (some_or_empty(&m.payload)) },
+            Some("ctcp") => (format_nick(some_or_empty(&m.nick))) " requested CTCP " span.ctcp { (some_or_empty(&m.payload)) },
+            Some(opcode) => "Unknown event: " (opcode),
         }
     }
 }
 
-fn message(m: &Message, sc: &ServerChannel, nicks: &Nicks, link_type: LinkType) -> Markup {
+fn message(m: &Message, sc: &ServerChannel, nicks: &Nicks, link_type: LinkType, tz: &Tz) -> Markup {
+    message_row(m, sc, nicks, link_type, false, tz)
+}
+
+/// A message, dimmed if it is only `context` around search results.
+fn message_row(
+    m: &Message,
+    sc: &ServerChannel,
+    nicks: &Nicks,
+    link_type: LinkType,
+    context: bool,
+    tz: &Tz,
+) -> Markup {
     let rel = match link_type {
-        LinkType::Absolute => uri!(route::channel(sc, m.timestamp.into())).to_string(),
+        LinkType::Absolute => uri!(route::channel(sc, Day::at(&m.timestamp, tz))).to_string(),
         _ => "".to_string(),
     };
+    let local = m.timestamp.with_timezone(tz);
     html! {
-        tr#(m.id_str()).msg data-timestamp=(m.epoch()) data-oper=(some_or_empty(&m.opcode)) {
-                td.ts { a.tslink title=(m.timestamp.to_rfc3339()) href={(rel) "#" (m.id_str())} { (m.timestamp.format("%H:%M")) } }
+        tr#(m.id_str()).msg.context[context] data-timestamp=(m.epoch()) data-oper=(some_or_empty(&m.opcode)) {
+                td.ts { a.tslink title=(local.to_rfc3339()) href={(rel) "#" (m.id_str())} { (local.format("%H:%M")) } }
                 @if m.is_talk() {
                     td.nick."me-tell"[m.is_me_tell()] { (format_nick(m.nick.as_deref().unwrap())) }
                 } @else {
@@ -381,10 +860,10 @@ fn message(m: &Message, sc: &ServerChannel, nicks: &Nicks, link_type: LinkType)
     }
 }
 
-fn render_calendar(day: &Day, info: &ChannelInfo, active_days: &HashSet<u32>) -> Markup {
+fn render_calendar(day: &Day, info: &ChannelInfo, active_days: &HashSet<u32>, tz: &Tz) -> Markup {
     let sc = &info.sc;
     let month = &calendar(day, active_days);
-    let today = &Day::today();
+    let today = &Day::today(tz);
     html! {
         section.calendar {
             nav {
@@ -464,8 +943,7 @@ fn calendar(day: &Day, active_days: &HashSet<u32>) -> OneMonth {
     let offset_monday = sow.weekday().num_days_from_monday() as usize;
 
     let mut days = 1..=num_days;
-    let first_week: OneWeek = core::iter::repeat(None)
-        .take(offset_monday)
+    let first_week: OneWeek = core::iter::repeat_n(None, offset_monday)
         .chain((1..=(7 - offset_monday)).map(|_| gen(days.next().unwrap() as u32)))
         .collect();
     let days = days.collect::<Vec<i64>>();
@@ -484,6 +962,127 @@ fn calendar(day: &Day, active_days: &HashSet<u32>) -> OneMonth {
     }
 }
 
+#[cfg(test)]
+fn render_line(line: ircjournal::IrcLine) -> String {
+    let m = ircjournal::line_to_new_message(
+        line,
+        &ServerChannel::new("libera", "#chan"),
+        chrono::Utc::now(),
+    )
+    .unwrap();
+    let m = Message {
+        id: 1,
+        channel: m.channel,
+        nick: m.nick,
+        line: m.line,
+        opcode: m.opcode,
+        oper_nick: m.oper_nick,
+        payload: m.payload,
+        timestamp: m.timestamp,
+        occurrence: m.occurrence,
+    };
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r#"<[^>]*>"#).unwrap();
+    }
+    TAG.replace_all(&format_message(&m, &Nicks::new()).into_string(), "")
+        .to_string()
+}
+
+#[test]
+fn test_format_message() {
+    use ircjournal::IrcLine;
+    let s = |s: &str| s.to_string();
+    assert_eq!(
+        render_line(IrcLine::Message {
+            nick: s("zopieux"),
+            line: s("hello")
+        }),
+        "hello"
+    );
+    assert_eq!(
+        render_line(IrcLine::Me {
+            nick: s("zopieux"),
+            line: s("waves")
+        }),
+        "zopieux waves"
+    );
+    assert_eq!(
+        render_line(IrcLine::Joined { nick: s("Tycale") }),
+        "Tycale has joined"
+    );
+    assert_eq!(
+        render_line(IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s("Cya")
+        }),
+        "Tycale has left (Cya)"
+    );
+    assert_eq!(
+        render_line(IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s("")
+        }),
+        "Tycale has left"
+    );
+    assert_eq!(
+        render_line(IrcLine::Quit {
+            nick: s("zop"),
+            reason: s("Quit: WeeChat 2.2")
+        }),
+        "zop has quit (Quit: WeeChat 2.2)"
+    );
+    assert_eq!(
+        render_line(IrcLine::Kicked {
+            oper_nick: s("zop"),
+            nick: s("haileda"),
+            reason: s("no u")
+        }),
+        "zop has kicked haileda (no u)"
+    );
+    assert_eq!(
+        render_line(IrcLine::TopicChanged {
+            nick: s("zopieux"),
+            old: s("Old topic"),
+            new: s("New topic")
+        }),
+        "zopieux changed the topic to New topic"
+    );
+    assert_eq!(
+        render_line(IrcLine::NickChanged {
+            old: s("zopieux"),
+            new: s("zop")
+        }),
+        "zopieux is now known as zop"
+    );
+    assert_eq!(
+        render_line(IrcLine::Notice {
+            nick: s("ChanServ"),
+            line: s("Welcome!")
+        }),
+        "Notice from ChanServ: Welcome!"
+    );
+    assert_eq!(
+        render_line(IrcLine::ModeChanged {
+            nick: s("ChanServ"),
+            mode: s("+o zopieux")
+        }),
+        "ChanServ sets mode +o zopieux"
+    );
+    assert_eq!(
+        render_line(IrcLine::Ctcp {
+            nick: s("haileda"),
+            command: s("VERSION")
+        }),
+        "haileda requested CTCP VERSION"
+    );
+    assert!(ircjournal::line_to_new_message(
+        IrcLine::Garbage,
+        &ServerChannel::new("libera", "#chan"),
+        chrono::Utc::now()
+    )
+    .is_none());
+}
+
 #[test]
 fn test_calendar() {
     let day = &Day(chrono::NaiveDate::from_ymd_opt(2021, 6, 22).unwrap_or_default());
@@ -532,3 +1131,21 @@ fn test_hl() {
         "<b>hello</b>world"
     );
 }
+
+#[test]
+fn test_thousands() {
+    assert_eq!(thousands(0), "0");
+    assert_eq!(thousands(999), "999");
+    assert_eq!(thousands(1_000), "1,000");
+    assert_eq!(thousands(10_000), "10,000");
+    assert_eq!(thousands(1_234_567), "1,234,567");
+}
+
+#[test]
+fn test_heat() {
+    assert_eq!(heat(0, 0), 0);
+    assert_eq!(heat(0, 10), 0);
+    assert_eq!(heat(1, 1000), 1);
+    assert_eq!(heat(500, 1000), 4);
+    assert_eq!(heat(1000, 1000), HEAT_LEVELS);
+}
@@ -1,12 +1,14 @@
 use rocket::fairing::AdHoc;
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc, time::Duration};
 use tokio::sync::broadcast;
 
 use ircjournal::{
     model::{Message, ServerChannel},
-    Database, MessageEvent,
+    Database,
 };
 
+use crate::{Day, MessageEvent};
+
 const CAPACITY: usize = 1024;
 const AWAKE_LISTEN_INTERVAL: Duration = Duration::from_secs(60);
 
@@ -29,10 +31,11 @@ pub fn broadcast_message_task(
                 Ok(notification) = listener.recv() => {
                     if let Ok(message) = serde_json::from_str::<Message>(notification.payload()) {
                         let sc = ServerChannel::from_str(message.channel.as_ref().unwrap()).unwrap();
-                        let nicks = crate::db::channel_info(&db, &sc, &message.timestamp.into()).await
+                        let day = Day::at(&message.timestamp, &chrono_tz::UTC);
+                        let nicks = crate::db::channel_info(&db, &sc, &day, &chrono_tz::UTC).await
                             .map(|info| info.nicks).unwrap_or_default();
-                        let _ = broadcast.send((sc.clone(), crate::view::formatted_message(&message, &nicks)));
                         debug!("New message for {:?}, id {}", &sc, message.id);
+                        let _ = broadcast.send((sc.clone(), Arc::new((message, nicks))));
                     }
                 },
             }
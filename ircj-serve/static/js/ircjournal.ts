@@ -1,9 +1,14 @@
 const kReconnectInterval = 5_000
 const kFlashLiveUpdates = true
+// How close to the end of the page more messages are loaded, in pixels.
+const kLoadMoreMargin = 2_000
 
 const kHighlightClass = "highlight"
 const kHideClass = "hide"
 const kHideJoinPartClass = "hide-join-part"
+const kHideNoticeClass = "hide-notice"
+const kHideModeClass = "hide-mode"
+const kHideCtcpClass = "hide-ctcp"
 
 function scrollToCentered(elem: HTMLElement) {
     elem.scrollIntoView({block: "center"})
@@ -70,6 +75,9 @@ function app() {
 
     let shiftPressed = false
     let filterInputDebounce = null
+    // Where to fetch the rest of a long day, null once it is all there.
+    let moreUrl: string = (messageTable.dataset as { more: string }).more || null
+    let loadingMore: Promise<boolean> = null
 
     function localLineFilter(filter: string) {
         if (filter.length) {
@@ -186,14 +194,64 @@ function app() {
 
     messageTable.querySelectorAll("a.tslink[href^='#']").forEach(instrumentForTsClick)
 
+    // Appends the next chunk of messages. Resolves to whether there may be more.
+    function loadMore(): Promise<boolean> {
+        if (!moreUrl) return Promise.resolve(false)
+        if (loadingMore) return loadingMore
+        const rows = messageTable.querySelectorAll(".msg")
+        const last = rows[rows.length - 1] as HTMLElement
+        loadingMore = fetch(`${moreUrl}?after=${last.id}`, {credentials: "same-origin"})
+            .then(r => r.ok ? r.text() : Promise.reject(r.statusText))
+            .then(html => {
+                const template = document.createElement("template")
+                template.innerHTML = html
+                const added = Array.from(template.content.querySelectorAll(".msg")) as HTMLElement[]
+                if (!added.length) moreUrl = null
+                added.forEach(row => {
+                    last.parentElement.appendChild(row)
+                    row.querySelectorAll("a.tslink[href^='#']").forEach(instrumentForTsClick)
+                })
+                // Apply the current selection and filter to the new lines.
+                onHashChange(false)
+                return moreUrl !== null
+            }, err => {
+                console.warn("cannot load more messages:", err)
+                return false
+            })
+            .then(more => {
+                loadingMore = null
+                return more
+            })
+        return loadingMore
+    }
+
+    function nearBottom(): boolean {
+        return bottomMark.getBoundingClientRect().top < window.innerHeight + kLoadMoreMargin
+    }
+
+    async function loadWhileNearBottom() {
+        while (nearBottom() && await loadMore()) { /* Keep filling the page. */ }
+    }
+
+    if (moreUrl) {
+        new IntersectionObserver(entries => {
+            if (entries.some(e => e.isIntersecting)) loadWhileNearBottom()
+        }, {rootMargin: `0px 0px ${kLoadMoreMargin}px 0px`}).observe(bottomMark)
+    }
+
     onHashChange(true)
 
-    const sel = firstSelectionTarget()
-    if (sel) {
-        const [highlighted] = findByIdOrTimestamp(sel)
-        if (highlighted) setTimeout(() => scrollToCentered(highlighted), 250)
+    async function revealSelection() {
+        const sel = firstSelectionTarget()
+        if (!sel) return
+        // The selected line may not be loaded yet.
+        let found = findByIdOrTimestamp(sel)
+        while (!found && await loadMore()) found = findByIdOrTimestamp(sel)
+        if (found) setTimeout(() => scrollToCentered(found[0]), 250)
     }
 
+    revealSelection()
+
     clearSelectionButton.addEventListener("click", (e) => {
         e.preventDefault()
         clearSelection()
@@ -221,6 +279,8 @@ function app() {
             }
         }
         liveStream.onmessage = (m) => {
+            // Until the day is fully loaded, new messages come with the next chunk.
+            if (moreUrl) return
             if (m.type === "message" && !!m.data) {
                 messageTable.insertAdjacentHTML("beforeend", m.data)
                 instrumentForTsClick(messageTable.lastElementChild as HTMLElement)
@@ -235,6 +295,18 @@ function app() {
         messageTable.classList.toggle(kHideJoinPartClass, !checked)
     }, true)
 
+    localCheckbox("show-notice", checked => {
+        messageTable.classList.toggle(kHideNoticeClass, !checked)
+    }, true)
+
+    localCheckbox("show-mode", checked => {
+        messageTable.classList.toggle(kHideModeClass, !checked)
+    }, true)
+
+    localCheckbox("show-ctcp", checked => {
+        messageTable.classList.toggle(kHideCtcpClass, !checked)
+    }, true)
+
     const liveCheckbox = localCheckbox("live", checked => {
         autoScroll.disabled = !checked
         if (checked) {
@@ -0,0 +1,195 @@
+use glob::{MatchOptions, Pattern};
+use std::path::{Component, Path, PathBuf};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Where to find log files: a single file, every file in a directory, or a glob pattern.
+pub enum Source {
+    File(PathBuf),
+    Dir(PathBuf),
+    Glob {
+        pattern: Pattern,
+        /// The longest leading directory without wildcards.
+        root: PathBuf,
+        /// Patterns matching the intermediate directories, from the shallowest.
+        dir_patterns: Vec<Pattern>,
+    },
+}
+
+fn has_wildcard(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+impl Source {
+    pub fn new(path: &Path) -> Self {
+        let path_str = path.to_string_lossy();
+        if has_wildcard(&path_str) {
+            if let Ok(pattern) = Pattern::new(&path_str) {
+                let components: Vec<Component> = path.components().collect();
+                let first_wildcard = components
+                    .iter()
+                    .position(|c| has_wildcard(&c.as_os_str().to_string_lossy()))
+                    .unwrap_or(0);
+                let root: PathBuf = components[..first_wildcard].iter().collect();
+                let dir_patterns = (first_wildcard + 1..components.len())
+                    .filter_map(|n| {
+                        let prefix: PathBuf = components[..n].iter().collect();
+                        Pattern::new(&prefix.to_string_lossy()).ok()
+                    })
+                    .collect();
+                return Self::Glob {
+                    pattern,
+                    root,
+                    dir_patterns,
+                };
+            }
+        }
+        if path.is_dir() {
+            Self::Dir(path.to_path_buf())
+        } else {
+            Self::File(path.to_path_buf())
+        }
+    }
+
+    /// Whether files are listed explicitly, rather than discovered.
+    pub fn is_explicit(&self) -> bool {
+        matches!(self, Self::File(_))
+    }
+
+    /// The log files currently present, sorted.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<_> = match self {
+            Self::File(path) => vec![path.clone()],
+            Self::Dir(dir) => std::fs::read_dir(dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok().map(|e| e.path()))
+                        .filter(|path| path.is_file())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            Self::Glob { pattern, .. } => glob::glob_with(pattern.as_str(), MATCH_OPTIONS)
+                .map(|paths| {
+                    paths
+                        .filter_map(Result::ok)
+                        .filter(|p| p.is_file())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        files.sort();
+        files
+    }
+
+    /// The directories to watch for new log files.
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        match self {
+            Self::File(_) => vec![],
+            Self::Dir(dir) => vec![dir.clone()],
+            Self::Glob {
+                root, dir_patterns, ..
+            } => std::iter::once(root.clone())
+                .chain(dir_patterns.iter().flat_map(|pattern| {
+                    glob::glob_with(pattern.as_str(), MATCH_OPTIONS)
+                        .map(|paths| {
+                            paths
+                                .filter_map(Result::ok)
+                                .filter(|p| p.is_dir())
+                                .collect()
+                        })
+                        .unwrap_or_else(|_| vec![])
+                }))
+                .filter(|dir| dir.is_dir())
+                .collect(),
+        }
+    }
+
+    /// Whether `path`, a new file, is a log file of this source.
+    pub fn matches(&self, path: &Path) -> bool {
+        match self {
+            Self::File(file) => file == path,
+            Self::Dir(dir) => path.parent() == Some(dir),
+            Self::Glob { pattern, .. } => pattern.matches_path_with(path, MATCH_OPTIONS),
+        }
+    }
+
+    /// Whether `dir`, a new directory, may contain log files of this source.
+    pub fn matches_dir(&self, dir: &Path) -> bool {
+        match self {
+            Self::Glob { dir_patterns, .. } => dir_patterns
+                .iter()
+                .any(|pattern| pattern.matches_path_with(dir, MATCH_OPTIONS)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Source;
+    use std::path::Path;
+
+    #[test]
+    fn test_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for name in [
+            "irc.libera.#a.weechatlog",
+            "libera/#b/2021-01-01.log",
+            "libera/#b/2021-01-02.log",
+            "libera/#c/2021-01-01.log",
+            "libera/#c/notes.txt",
+        ] {
+            let path = root.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let file = Source::new(&root.join("irc.libera.#a.weechatlog"));
+        assert!(file.is_explicit());
+        assert_eq!(file.files(), vec![root.join("irc.libera.#a.weechatlog")]);
+        assert!(file.dirs().is_empty());
+
+        let channel_dir = Source::new(&root.join("libera/#b"));
+        assert!(!channel_dir.is_explicit());
+        assert_eq!(
+            channel_dir.files(),
+            vec![
+                root.join("libera/#b/2021-01-01.log"),
+                root.join("libera/#b/2021-01-02.log")
+            ]
+        );
+        assert_eq!(channel_dir.dirs(), vec![root.join("libera/#b")]);
+        assert!(channel_dir.matches(&root.join("libera/#b/2021-01-03.log")));
+        assert!(!channel_dir.matches(&root.join("libera/#c/2021-01-03.log")));
+
+        let glob = Source::new(&root.join("libera/*/*.log"));
+        assert!(!glob.is_explicit());
+        assert_eq!(
+            glob.files(),
+            vec![
+                root.join("libera/#b/2021-01-01.log"),
+                root.join("libera/#b/2021-01-02.log"),
+                root.join("libera/#c/2021-01-01.log"),
+            ]
+        );
+        assert_eq!(
+            glob.dirs(),
+            vec![
+                root.join("libera"),
+                root.join("libera/#b"),
+                root.join("libera/#c"),
+            ]
+        );
+        assert!(glob.matches(&root.join("libera/#d/2021-01-01.log")));
+        assert!(!glob.matches(&root.join("libera/#d/notes.txt")));
+        assert!(!glob.matches(&root.join("libera/#d/nested/2021-01-01.log")));
+        assert!(glob.matches_dir(&root.join("libera/#d")));
+        assert!(!glob.matches_dir(&root.join("libera/#d/nested")));
+        assert!(!glob.matches_dir(Path::new("/elsewhere")));
+    }
+}
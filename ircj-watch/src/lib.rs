@@ -1,17 +1,58 @@
+use chrono_tz::Tz;
 use futures::StreamExt;
 use indicatif::ProgressBar;
-use std::{marker::PhantomData, path::Path};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, AsyncSeekExt, BufReader, SeekFrom},
+    io::{AsyncBufReadExt, BufReader},
 };
 use tokio_stream::wrappers::LinesStream;
 
+pub mod discover;
+pub mod tailer;
+
 use ircjournal::{
-    db::batch_insert_messages, line_to_new_message, model::ServerChannel, seek_past_line, Database,
-    Logger, NewMessage, ParseResult,
+    db::batch_insert_messages,
+    format::{AnyLogger, LogFormat, SNIFF_LINES},
+    line_to_new_message,
+    model::ServerChannel,
+    scan_past_line, seek_past_line, seek_to_end, Database, LineParser, NewMessage, Occurrences,
+    ParseResult,
 };
 
+/// PostgreSQL text search configurations, used to stem words in full-text search, eg. "french",
+/// or "simple" not to stem them.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SearchConfigs {
+    pub default: String,
+    /// Per channel, eg. `"libera/#chan" = "simple"`.
+    pub channels: HashMap<String, String>,
+}
+
+impl Default for SearchConfigs {
+    fn default() -> Self {
+        Self {
+            default: "english".to_owned(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+impl SearchConfigs {
+    pub fn of(&self, sc: &ServerChannel) -> &str {
+        self.channels.get(&sc.to_string()).unwrap_or(&self.default)
+    }
+
+    /// The configurations in use, to check that they exist.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.default.as_str()).chain(self.channels.values().map(String::as_str))
+    }
+}
+
 fn invalid_input(msg: &str) -> std::io::Error {
     std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
 }
@@ -34,20 +75,71 @@ pub async fn inserter_task(
     total
 }
 
-pub async fn backfill<L: Logger>(
+/// Resolves the logger for `path`, sniffing its first lines if `format` is [`LogFormat::Auto`].
+pub async fn open_logger(
     path: &Path,
+    format: LogFormat,
+    timezone: Tz,
+) -> std::io::Result<(ServerChannel, AnyLogger)> {
+    let format = match format {
+        LogFormat::Auto => {
+            let f = File::open(path).await?;
+            let lines: Vec<String> = LinesStream::new(BufReader::new(f).lines())
+                .take(SNIFF_LINES)
+                .filter_map(|line| async move { line.ok() })
+                .collect()
+                .await;
+            LogFormat::sniff(path, &lines)
+                .ok_or_else(|| invalid_input("could not detect log format"))?
+        }
+        format => format,
+    };
+    let (sc, logger) =
+        AnyLogger::open(format, path).ok_or_else(|| invalid_input("not a valid filename"))?;
+    Ok((sc, logger.with_timezone(timezone)))
+}
+
+/// Finds the file following `path`, for logs that roll over to a new file periodically.
+pub async fn next_log_path(logger: &AnyLogger, path: &Path) -> Option<PathBuf> {
+    if !logger.rolls_over() {
+        return None;
+    }
+    let mut entries = tokio::fs::read_dir(path.parent()?).await.ok()?;
+    let mut next: Option<PathBuf> = None;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let candidate = entry.path();
+        if logger.continues(path, &candidate)
+            && next
+                .as_ref()
+                .is_none_or(|next| logger.continues(&candidate, next))
+        {
+            next = Some(candidate);
+        }
+    }
+    next
+}
+
+/// Reads `path` past the last message saved for its channel, sending the new messages to `tx`.
+/// `occurrences` carries over to the rest of the log, be it the same file or the next one.
+#[allow(clippy::too_many_arguments)]
+pub async fn backfill(
+    path: &Path,
+    sc: ServerChannel,
+    mut logger: AnyLogger,
+    occurrences: &mut Occurrences,
     db: &Database,
+    search_configs: &SearchConfigs,
     backfill: bool,
     tx: tokio::sync::mpsc::Sender<NewMessage>,
     progress: ProgressBar,
-) -> std::io::Result<(ServerChannel, BufReader<File>, PhantomData<L>)> {
-    let sc = L::parse_path(path).ok_or_else(|| invalid_input("not a valid filename"))?;
+) -> std::io::Result<(ServerChannel, BufReader<File>, AnyLogger)> {
     let f = File::open(path).await?;
     let mut reader = tokio::io::BufReader::new(f);
+    ircjournal::db::save_channel(db, &sc, Some(search_configs.of(&sc))).await;
 
     if !backfill {
-        reader.seek(SeekFrom::End(0)).await?;
-        return Ok((sc, reader, PhantomData));
+        seek_to_end(&mut logger, &mut reader).await?;
+        return Ok((sc, reader, logger));
     }
 
     // Do we have a last message in the DB already?
@@ -55,9 +147,15 @@ pub async fn backfill<L: Logger>(
     let sc_ = sc.clone();
     if let Some(ts) = ircjournal::db::last_message_ts(db, &sc_).await {
         // If so, before reading further, seek past it.
-        last_ts = seek_past_line::<L, _>(&mut reader, &ts)
-            .await
-            .map(|(ts, _)| ts);
+        last_ts = match seek_past_line(&mut logger, &mut reader, &ts).await {
+            Some((ts, _)) => Some(ts),
+            // Bisection failed, eg. the file is entirely older or newer than the last message.
+            // Resume past the last older line instead, if any.
+            None if logger.is_self_dated() => scan_past_line(&mut logger, &mut reader, &ts)
+                .await
+                .map(|(ts, _)| ts),
+            None => None,
+        };
     }
 
     let from_str = match last_ts {
@@ -70,16 +168,15 @@ pub async fn backfill<L: Logger>(
     let mut line_stream = LinesStream::new(reader.lines());
     line_stream
         .by_ref() // This is key: we need to grab the inner BufRead to tell position afterwards.
-        .zip(futures::stream::repeat((sc.clone(), tx, progress.clone())))
-        .filter_map(|(line, (sc, tx, p))| async move {
-            let line = line.ok()?;
-            match L::parse_line(&line) {
-                ParseResult::Ok((ts, line)) => {
-                    line_to_new_message(line, &sc, ts).map(|nm| (nm, tx, p))
-                }
-                _ => None,
+        .filter_map(|line| async move { line.ok() })
+        .map(|line| match logger.parse_line(&line) {
+            ParseResult::Ok((ts, line)) => {
+                line_to_new_message(line, &sc, ts).map(|m| occurrences.number(m))
             }
+            _ => None,
         })
+        .zip(futures::stream::repeat((tx, progress.clone())))
+        .filter_map(|(message, (tx, p))| async move { message.map(|m| (m, tx, p)) })
         .for_each(|(message, tx, p)| async move {
             tx.send(message).await.expect("channel closed");
             p.inc_length(1);
@@ -87,5 +184,36 @@ pub async fn backfill<L: Logger>(
         })
         .await;
     progress.finish_with_message(format!("{} (done)", from_str));
-    Ok((sc, line_stream.into_inner().into_inner(), PhantomData))
+    Ok((sc, line_stream.into_inner().into_inner(), logger))
+}
+
+/// Backfills a log discovered while watching, and waits for all its messages to be saved.
+pub async fn backfill_discovered(
+    path: &Path,
+    sc: ServerChannel,
+    logger: AnyLogger,
+    occurrences: &mut Occurrences,
+    db: &Database,
+    search_configs: &SearchConfigs,
+    batch_size: usize,
+) -> std::io::Result<(ServerChannel, BufReader<File>, AnyLogger)> {
+    let (tx, rx) = tokio::sync::mpsc::channel::<NewMessage>(128);
+    let db_for_inserter = db.clone();
+    let inserter_handle =
+        tokio::spawn(async move { inserter_task(batch_size, db_for_inserter, rx).await });
+    // A new log is never seen before, so always read it whole.
+    let res = backfill(
+        path,
+        sc,
+        logger,
+        occurrences,
+        db,
+        search_configs,
+        true,
+        tx,
+        ProgressBar::hidden(),
+    )
+    .await;
+    inserter_handle.await.unwrap();
+    res
 }
@@ -1,29 +1,72 @@
+use chrono_tz::Tz;
 use figment::{providers::Format, Figment};
 use futures::StreamExt;
+use inotify::{EventMask, WatchDescriptor, WatchMask};
 use itertools::{Either, Itertools};
-use log::debug;
-use pin_project_lite::pin_project;
-use std::{collections::HashMap, marker::PhantomData, path::PathBuf, pin::Pin};
-use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, BufReader},
+use log::{debug, info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
 };
-use tokio_stream::wrappers::LinesStream;
 
-use ircj_watch::{backfill, inserter_task};
+use ircj_watch::{
+    backfill, backfill_discovered, discover::Source, inserter_task, next_log_path, open_logger,
+    tailer::Tailer, SearchConfigs,
+};
 use ircjournal::{
-    line_to_new_message,
-    model::{NewMessage, ServerChannel},
-    Logger, ParseResult,
+    format::{AnyLogger, LogFormat},
+    model::NewMessage,
+    Database, Occurrences,
 };
 
+/// Either a bare path, using [`LogFormat::Auto`] and the global time zone, or a path with an
+/// explicit format and/or time zone.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum WatchedPath {
+    Path(PathBuf),
+    WithFormat {
+        path: PathBuf,
+        #[serde(default)]
+        format: LogFormat,
+        #[serde(default)]
+        timezone: Option<Tz>,
+    },
+}
+
+impl WatchedPath {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::WithFormat { path, .. } => path,
+        }
+    }
+
+    fn format(&self) -> LogFormat {
+        match self {
+            Self::Path(_) => LogFormat::Auto,
+            Self::WithFormat { format, .. } => *format,
+        }
+    }
+
+    fn timezone(&self) -> Option<Tz> {
+        match self {
+            Self::Path(_) => None,
+            Self::WithFormat { timezone, .. } => *timezone,
+        }
+    }
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 struct Config {
     db: String,
-    paths: Vec<PathBuf>,
+    paths: Vec<WatchedPath>,
+    /// The time zone log timestamps are written in, unless set per path.
+    timezone: Tz,
     backfill: bool,
     backfill_batch_size: usize,
     backfill_concurrency: usize,
+    search: SearchConfigs,
 }
 
 impl Default for Config {
@@ -31,14 +74,17 @@ impl Default for Config {
         Self {
             db: "".to_owned(),
             paths: vec![],
+            timezone: chrono_tz::UTC,
             backfill: true,
             backfill_batch_size: 5_000,
             backfill_concurrency: 2,
+            search: SearchConfigs::default(),
         }
     }
 }
 
 #[tokio::main]
+#[allow(clippy::result_large_err)]
 async fn main() -> Result<(), figment::Error> {
     env_logger::init();
 
@@ -51,6 +97,49 @@ async fn main() -> Result<(), figment::Error> {
     let pool = ircjournal::db::create_db(&config.db)
         .await
         .unwrap_or_else(|_| panic!("Connecting and migrating the database at {}", &config.db));
+    for name in config.search.names() {
+        if !ircjournal::db::search_config_exists(&pool, name).await {
+            panic!("Unknown text search configuration {}", name);
+        }
+    }
+
+    let sources: Vec<_> = config
+        .paths
+        .iter()
+        .map(|watched| {
+            let timezone = watched.timezone().unwrap_or(config.timezone);
+            (Source::new(watched.path()), watched.format(), timezone)
+        })
+        .collect();
+
+    // Find the logs present so far. Only report errors for files that were explicitly requested.
+    let mut candidates = Vec::new();
+    let mut seen = HashSet::new();
+    for (source, format, timezone) in &sources {
+        for path in source.files() {
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            match open_logger(&path, *format, *timezone).await {
+                Ok(opened) => candidates.push((path, Ok(opened))),
+                Err(err) if source.is_explicit() => candidates.push((path, Err(err))),
+                Err(err) => debug!("Ignoring {}: {}", path.display(), err),
+            }
+        }
+    }
+    // Later files of a log that rolls over are caught up with from the earliest one.
+    let continued: HashSet<_> = candidates
+        .iter()
+        .filter_map(|(path, opened)| Some((path, &opened.as_ref().ok()?.1)))
+        .flat_map(|(path, logger)| {
+            candidates
+                .iter()
+                .map(|(next, _)| next)
+                .filter(move |next| logger.continues(path, next))
+        })
+        .cloned()
+        .collect();
+    candidates.retain(|(path, _)| !continued.contains(path));
 
     // First, backfill.
     let prog = indicatif::MultiProgress::new();
@@ -65,10 +154,10 @@ async fn main() -> Result<(), figment::Error> {
         tokio::spawn(async move { inserter_task(batch_size, db_for_inserter, rx).await });
 
     let do_backfill = config.backfill;
-    let prepared: Vec<_> = config
-        .paths
-        .iter()
-        .map(|path| {
+    let search_configs = &config.search;
+    let prepared: Vec<_> = candidates
+        .into_iter()
+        .map(|(path, opened)| {
             let name = path
                 .file_name()
                 .unwrap_or_default()
@@ -80,7 +169,7 @@ async fn main() -> Result<(), figment::Error> {
                     .with_style(sty.clone()),
             );
             p.tick();
-            (path, p, pool.clone(), tx.clone())
+            (path, opened, p, pool.clone(), tx.clone())
         })
         .collect();
 
@@ -91,20 +180,43 @@ async fn main() -> Result<(), figment::Error> {
     });
 
     let results: Vec<_> = futures::stream::iter(prepared)
-        .map(|(path, progress, pool, tx)| async move {
-            // TODO: generify.
-            (
-                path.clone(),
-                backfill::<ircjournal::weechat::Weechat>(
-                    path,
+        .map(|(mut path, opened, progress, pool, tx)| async move {
+            let res = async {
+                let (sc, logger) = opened?;
+                let mut occurrences = Occurrences::default();
+                let mut res = backfill(
+                    &path,
+                    sc,
+                    logger,
+                    &mut occurrences,
                     &pool,
+                    search_configs,
                     do_backfill,
-                    tx,
+                    tx.clone(),
                     progress.clone(),
                 )
-                .await,
-                progress,
-            )
+                .await?;
+                // Catch up with the files the log rolled over to since, if any.
+                while let Some(next) = next_log_path(&res.2, &path).await {
+                    let (sc, logger) = open_logger(&next, res.2.format(), res.2.timezone()).await?;
+                    res = backfill(
+                        &next,
+                        sc,
+                        logger,
+                        &mut occurrences,
+                        &pool,
+                        search_configs,
+                        do_backfill,
+                        tx.clone(),
+                        progress.clone(),
+                    )
+                    .await?;
+                    path = next;
+                }
+                Ok::<_, std::io::Error>((res, occurrences))
+            }
+            .await;
+            (path, res, progress)
         })
         .buffer_unordered(config.backfill_concurrency)
         .collect()
@@ -126,73 +238,265 @@ async fn main() -> Result<(), figment::Error> {
     ins.unwrap();
     prog.unwrap();
 
-    if successes.is_empty() {
+    // Now watch for changes and save new messages as they come.
+    let mut watcher = Watcher {
+        notifier: inotify::Inotify::init().unwrap(),
+        pool,
+        search_configs: config.search.clone(),
+        batch_size,
+        sources,
+        dir_of_wd: HashMap::new(),
+        tailer_of_wd: HashMap::new(),
+        moved: Vec::new(),
+    };
+    for (path, ((sc, buf_reader, logger), occurrences)) in successes {
+        watcher.add_tailer(Tailer::new(path, logger, sc, occurrences, buf_reader));
+    }
+    let dirs: Vec<_> = watcher
+        .sources
+        .iter()
+        .flat_map(|(source, ..)| source.dirs())
+        .collect();
+    dirs.iter().for_each(|dir| watcher.watch_dir(dir));
+    // Pick up the logs that appeared before their directory was watched.
+    let files: Vec<_> = watcher
+        .sources
+        .iter()
+        .flat_map(|(source, ..)| source.files())
+        .filter(|path| !watcher.is_known(path))
+        .collect();
+    for path in files {
+        watcher.on_created(path).await;
+    }
+
+    if watcher.tailer_of_wd.is_empty() && watcher.moved.is_empty() && watcher.dir_of_wd.is_empty() {
         eprintln!("Could not observe any of the requested files. Exiting.");
         std::process::exit(1);
     }
 
-    // Now watch for changes and save new messages as they come.
-    let mut notifier = inotify::Inotify::init().unwrap();
-
-    let mut tailer_of_wd: HashMap<_, _> = successes
-        .into_iter()
-        .map(|(path, (sc, buf_reader, type_mark))| {
-            (
-                notifier
-                    .add_watch(path, inotify::WatchMask::MODIFY)
-                    .unwrap(),
-                Tailer::new(type_mark, sc, buf_reader),
-            )
-        })
-        .collect();
-
-    let mut notify_stream = notifier.event_stream([0; 32]).expect("event stream").fuse();
+    let mut notify_stream = watcher
+        .notifier
+        .event_stream([0; 1024])
+        .expect("event stream")
+        .fuse();
 
     loop {
         tokio::select! {
             Some(Ok(event)) = notify_stream.next() => {
-                // A file has changed. Get its associated tailed, read new lines, save them.
-                let tailer = tailer_of_wd.get_mut(&event.wd).unwrap();
-                let sc = tailer.sc.clone();
-                let new_messages = Pin::new(tailer).read_all_new_lines().await;
-                let inserted = ircjournal::db::batch_insert_messages_and_notify(&pool, &new_messages).await;
-                debug!("Channel {}: inserted {}", &sc, inserted.unwrap_or_default());
+                if let Some(dir) = watcher.dir_of_wd.get(&event.wd) {
+                    // A file or directory appeared in a watched directory.
+                    if let Some(name) = event.name {
+                        let path = dir.join(name);
+                        watcher.on_created(path).await;
+                    }
+                } else if event.mask.intersects(EventMask::MOVE_SELF | EventMask::DELETE_SELF) {
+                    // A file was moved away or deleted, eg. rotated.
+                    if watcher.tailer_of_wd.contains_key(&event.wd) {
+                        watcher.rewatch(event.wd).await;
+                    }
+                } else if let Some(tailer) = watcher.tailer_of_wd.get_mut(&event.wd) {
+                    // A file has changed. Read new lines, save them.
+                    ingest(&watcher.pool, tailer).await;
+                }
             }
         }
     }
 }
 
-pin_project! {
-    struct Tailer<L: Logger> {
-        type_mark: PhantomData<L>,
-        sc: ServerChannel,
-        #[pin]
-        buf_reader: BufReader<File>,
-    }
+const TAILER_MASK: WatchMask = WatchMask::MODIFY
+    .union(WatchMask::MOVE_SELF)
+    .union(WatchMask::DELETE_SELF);
+
+/// The state of the watch phase: which files are tailed, and where to look for new ones.
+struct Watcher {
+    notifier: inotify::Inotify,
+    pool: Database,
+    search_configs: SearchConfigs,
+    batch_size: usize,
+    sources: Vec<(Source, LogFormat, Tz)>,
+    dir_of_wd: HashMap<WatchDescriptor, PathBuf>,
+    tailer_of_wd: HashMap<WatchDescriptor, Tailer<AnyLogger>>,
+    /// Tailers whose file was moved away, waiting for it to be recreated.
+    moved: Vec<Tailer<AnyLogger>>,
 }
 
-impl<L: Logger> Tailer<L> {
-    fn new(type_mark: PhantomData<L>, sc: ServerChannel, buf_reader: BufReader<File>) -> Self {
-        Self {
-            type_mark,
-            sc,
-            buf_reader,
+impl Watcher {
+    fn watch_dir(&mut self, dir: &Path) {
+        match self
+            .notifier
+            .add_watch(dir, WatchMask::CREATE | WatchMask::MOVED_TO)
+        {
+            Ok(wd) => {
+                self.dir_of_wd.insert(wd, dir.to_path_buf());
+            }
+            Err(err) => warn!("Cannot watch directory {}: {}", dir.display(), err),
         }
     }
 
-    async fn read_all_new_lines(self: Pin<&mut Self>) -> Vec<NewMessage> {
-        let this = self.project();
-        LinesStream::new(this.buf_reader.lines())
-            .by_ref()
-            .filter_map(|line| async move { line.ok() })
-            .zip(futures::stream::repeat(this.sc.clone()))
-            .filter_map(|(line, sc)| async move {
-                match L::parse_line(&line) {
-                    ParseResult::Ok((ts, line)) => line_to_new_message(line, &sc, ts),
-                    _ => None,
+    fn add_tailer(&mut self, tailer: Tailer<AnyLogger>) {
+        // Watch for the file to be recreated after rotation, and for the next file of logs that
+        // roll over.
+        if let Some(dir) = tailer.path.parent() {
+            self.watch_dir(dir);
+        }
+        match self.notifier.add_watch(&tailer.path, TAILER_MASK) {
+            Ok(wd) => {
+                self.tailer_of_wd.insert(wd, tailer);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!(
+                    "Channel {}: waiting for {}",
+                    &tailer.sc,
+                    tailer.path.display()
+                );
+                self.moved.push(tailer);
+            }
+            Err(err) => warn!("Channel {}: cannot watch: {}", &tailer.sc, err),
+        }
+    }
+
+    /// Whether `path` is tailed, or is an earlier file of a tailed log.
+    fn is_known(&self, path: &Path) -> bool {
+        self.tailer_of_wd
+            .values()
+            .chain(&self.moved)
+            .any(|tailer| tailer.path == path || tailer.logger.continues(path, &tailer.path))
+    }
+
+    /// Watches the file now at the path of the tailer at `wd`, after reading what is left of the
+    /// previous one.
+    async fn rewatch(&mut self, wd: WatchDescriptor) {
+        let mut tailer = self.tailer_of_wd.remove(&wd).unwrap();
+        let _ = self.notifier.rm_watch(wd);
+        ingest(&self.pool, &mut tailer).await;
+        self.add_tailer(tailer);
+    }
+
+    /// Handles `path` appearing in a watched directory.
+    async fn on_created(&mut self, path: PathBuf) {
+        let mut pending = vec![path];
+        while let Some(path) = pending.pop() {
+            if path.is_dir() {
+                if !self
+                    .sources
+                    .iter()
+                    .any(|(source, ..)| source.matches_dir(&path))
+                {
+                    continue;
+                }
+                self.watch_dir(&path);
+                // Files may have been created before the watch was added.
+                if let Ok(entries) = std::fs::read_dir(&path) {
+                    let mut entries: Vec<_> =
+                        entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+                    entries.sort();
+                    pending.extend(entries.into_iter().rev());
                 }
-            })
-            .collect()
-            .await
+                continue;
+            }
+            if let Some(i) = self.moved.iter().position(|tailer| tailer.path == path) {
+                // A rotated log was recreated.
+                let mut tailer = self.moved.remove(i);
+                ingest(&self.pool, &mut tailer).await;
+                self.add_tailer(tailer);
+                continue;
+            }
+            let replaced_wd = self
+                .tailer_of_wd
+                .iter()
+                .find(|(_, tailer)| tailer.path == path)
+                .map(|(wd, _)| wd.clone());
+            if let Some(wd) = replaced_wd {
+                // Another file was moved in place of a tailed log.
+                self.rewatch(wd).await;
+                continue;
+            }
+            let rolled_wd = self
+                .tailer_of_wd
+                .iter()
+                .find(|(_, tailer)| tailer.logger.continues(&tailer.path, &path))
+                .map(|(wd, _)| wd.clone());
+            if let Some(wd) = rolled_wd {
+                self.roll_over(wd, path).await;
+            } else if !self.is_known(&path) {
+                let found = self
+                    .sources
+                    .iter()
+                    .find(|(source, ..)| source.matches(&path))
+                    .map(|(_, format, timezone)| (*format, *timezone));
+                if let Some((format, timezone)) = found {
+                    self.discover(path, format, timezone).await;
+                }
+            }
+        }
     }
+
+    /// Switches the tailer at `wd` to `next`, the file its log rolled over to.
+    async fn roll_over(&mut self, wd: WatchDescriptor, next: PathBuf) {
+        let mut tailer = self.tailer_of_wd.remove(&wd).unwrap();
+        let _ = self.notifier.rm_watch(wd);
+        // Save whatever was left in the previous file first.
+        ingest(&self.pool, &mut tailer).await;
+        if let Err(err) = tailer.roll_over(next).await {
+            warn!("Channel {}: cannot follow rollover: {}", &tailer.sc, err);
+            return;
+        }
+        ingest(&self.pool, &mut tailer).await;
+        self.add_tailer(tailer);
+    }
+
+    /// Backfills and starts tailing `path`, a log that was not there before.
+    async fn discover(&mut self, path: PathBuf, format: LogFormat, timezone: Tz) {
+        let (sc, logger) = match open_logger(&path, format, timezone).await {
+            Ok(opened) => opened,
+            Err(err) => {
+                debug!("Ignoring {}: {}", path.display(), err);
+                return;
+            }
+        };
+        // Watch before reading, so that lines written meanwhile are not missed.
+        let wd = match self.notifier.add_watch(&path, TAILER_MASK) {
+            Ok(wd) => wd,
+            Err(err) => {
+                warn!("Channel {}: cannot watch: {}", &sc, err);
+                return;
+            }
+        };
+        let mut occurrences = Occurrences::default();
+        let backfilled = backfill_discovered(
+            &path,
+            sc,
+            logger,
+            &mut occurrences,
+            &self.pool,
+            &self.search_configs,
+            self.batch_size,
+        )
+        .await;
+        match backfilled {
+            Ok((sc, buf_reader, logger)) => {
+                info!("Channel {}: discovered {}", &sc, path.display());
+                if let Some(dir) = path.parent() {
+                    self.watch_dir(dir);
+                }
+                self.tailer_of_wd
+                    .insert(wd, Tailer::new(path, logger, sc, occurrences, buf_reader));
+            }
+            Err(err) => {
+                warn!("Cannot backfill {}: {}", path.display(), err);
+                let _ = self.notifier.rm_watch(wd);
+            }
+        }
+    }
+}
+
+/// Reads all new lines from `tailer` and saves them.
+async fn ingest(pool: &Database, tailer: &mut Tailer<AnyLogger>) {
+    let new_messages = tailer.read_all_new_lines().await;
+    let inserted = ircjournal::db::batch_insert_messages_and_notify(pool, &new_messages).await;
+    debug!(
+        "Channel {}: inserted {}",
+        &tailer.sc,
+        inserted.unwrap_or_default()
+    );
 }
@@ -0,0 +1,178 @@
+use futures::StreamExt;
+use log::{debug, warn};
+use pin_project_lite::pin_project;
+use std::{os::unix::fs::MetadataExt, path::PathBuf, pin::Pin};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+};
+use tokio_stream::wrappers::LinesStream;
+
+use ircjournal::{
+    format::AnyLogger, line_to_new_message, model::ServerChannel, LineParser, NewMessage,
+    Occurrences, ParseResult,
+};
+
+pin_project! {
+    /// Follows a log file as it grows.
+    pub struct Tailer<P: LineParser> {
+        pub path: PathBuf,
+        pub logger: P,
+        pub sc: ServerChannel,
+        occurrences: Occurrences,
+        #[pin]
+        buf_reader: BufReader<File>,
+    }
+}
+
+impl<P: LineParser> Tailer<P> {
+    pub fn new(
+        path: PathBuf,
+        logger: P,
+        sc: ServerChannel,
+        occurrences: Occurrences,
+        buf_reader: BufReader<File>,
+    ) -> Self {
+        Self {
+            path,
+            logger,
+            sc,
+            occurrences,
+            buf_reader,
+        }
+    }
+
+    async fn read_lines(self: Pin<&mut Self>) -> Vec<NewMessage> {
+        let this = self.project();
+        let (logger, sc, occurrences) = (this.logger, this.sc, this.occurrences);
+        let lines: Vec<String> = LinesStream::new(this.buf_reader.lines())
+            .filter_map(|line| async move { line.ok() })
+            .collect()
+            .await;
+        lines
+            .iter()
+            .filter_map(|line| match logger.parse_line(line) {
+                ParseResult::Ok((ts, line)) => {
+                    line_to_new_message(line, sc, ts).map(|m| occurrences.number(m))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the file at `path` is no longer the one being read (eg. moved away by logrotate
+    /// and recreated), or was truncated below the current position (eg. logrotate's
+    /// `copytruncate`). A file moved away and not recreated yet keeps being read.
+    async fn was_rotated(&mut self) -> std::io::Result<bool> {
+        let current = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let opened = self.buf_reader.get_ref().metadata().await?;
+        if (opened.dev(), opened.ino()) != (current.dev(), current.ino()) {
+            return Ok(true);
+        }
+        Ok(current.len() < self.buf_reader.stream_position().await?)
+    }
+}
+
+impl Tailer<AnyLogger> {
+    /// Reads all new lines. If the log was rotated, finishes reading the previous file, then
+    /// resumes from the start of the new one.
+    pub async fn read_all_new_lines(&mut self) -> Vec<NewMessage> {
+        let mut messages = Pin::new(&mut *self).read_lines().await;
+        match self.was_rotated().await {
+            Ok(false) => {}
+            Ok(true) => {
+                debug!("Channel {}: log rotated, reopening", &self.sc);
+                match self.roll_over(self.path.clone()).await {
+                    Ok(()) => messages.extend(Pin::new(&mut *self).read_lines().await),
+                    Err(err) => warn!("Channel {}: cannot reopen: {}", &self.sc, err),
+                }
+            }
+            Err(err) => warn!("Channel {}: cannot check for rotation: {}", &self.sc, err),
+        }
+        messages
+    }
+
+    /// Switches to the start of `next`, eg. the file the log rolled over to.
+    pub async fn roll_over(&mut self, next: PathBuf) -> std::io::Result<()> {
+        let (sc, logger) = AnyLogger::open(self.logger.format(), &next).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a valid filename")
+        })?;
+        self.buf_reader = BufReader::new(File::open(&next).await?);
+        self.logger = logger.with_timezone(self.logger.timezone());
+        self.sc = sc;
+        self.path = next;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tailer;
+    use ircjournal::{
+        format::{AnyLogger, LogFormat},
+        Occurrences,
+    };
+    use std::{io::Write, path::Path};
+    use tokio::{fs::File, io::BufReader};
+
+    fn append(path: &Path, lines: &[&str]) {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        for line in lines {
+            writeln!(f, "2020-01-25 09:31:14\thaileda\t{}", line).unwrap();
+        }
+    }
+
+    async fn read(tailer: &mut Tailer<AnyLogger>) -> Vec<String> {
+        tailer
+            .read_all_new_lines()
+            .await
+            .into_iter()
+            .map(|m| m.line.unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("irc.libera.#chan.weechatlog");
+        append(&path, &["one"]);
+        let (sc, logger) = AnyLogger::open(LogFormat::Weechat, &path).unwrap();
+        let buf_reader = BufReader::new(File::open(&path).await.unwrap());
+        let mut tailer = Tailer::new(path.clone(), logger, sc, Occurrences::default(), buf_reader);
+        assert_eq!(read(&mut tailer).await, vec!["one"]);
+        append(&path, &["two"]);
+        assert_eq!(read(&mut tailer).await, vec!["two"]);
+
+        // Moved away, not recreated yet: keep reading the old file.
+        let rotated = dir.path().join("irc.libera.#chan.weechatlog.1");
+        std::fs::rename(&path, &rotated).unwrap();
+        append(&rotated, &["three"]);
+        assert_eq!(read(&mut tailer).await, vec!["three"]);
+
+        // Recreated: finish the old file, then read the new one from the start.
+        append(&rotated, &["four"]);
+        append(&path, &["five"]);
+        assert_eq!(read(&mut tailer).await, vec!["four", "five"]);
+        append(&path, &["six"]);
+        assert_eq!(read(&mut tailer).await, vec!["six"]);
+
+        // Truncated in place.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append(&path, &["seven"]);
+        assert_eq!(read(&mut tailer).await, vec!["seven"]);
+        assert_eq!(read(&mut tailer).await, Vec::<String>::new());
+    }
+}
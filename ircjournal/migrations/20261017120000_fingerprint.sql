@@ -0,0 +1,17 @@
+-- Identical lines logged within the same second are numbered in log order, so that a line
+-- ingested twice has the same fingerprint as the first time.
+ALTER TABLE "message"
+    ADD COLUMN "occurrence" smallint NOT NULL DEFAULT 0;
+
+-- Existing duplicates are numbered the same way, and kept.
+UPDATE "message" m
+SET "occurrence" = n."occurrence"
+FROM (SELECT "id",
+             (row_number() OVER (PARTITION BY "channel", "timestamp", coalesce("nick", ''), coalesce("opcode", ''),
+                 coalesce("line", '') ORDER BY "id") - 1)::smallint "occurrence"
+      FROM "message") n
+WHERE m."id" = n."id"
+  AND n."occurrence" > 0;
+
+CREATE UNIQUE INDEX "fingerprint" ON "message" ("channel", "timestamp", coalesce("nick", ''), coalesce("opcode", ''),
+                                                coalesce("line", ''), "occurrence");
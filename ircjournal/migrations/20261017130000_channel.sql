@@ -0,0 +1,12 @@
+-- Private channels, eg. queries with a single user, are only shown to authorized viewers.
+-- Ingestion classifies new channels by name, change "private" to override.
+CREATE TABLE "channel"
+(
+    "name"    text PRIMARY KEY NOT NULL,
+    "private" boolean          NOT NULL
+);
+
+-- Channel names start with a channel type prefix, as in ServerChannel::is_query.
+INSERT INTO "channel" ("name", "private")
+SELECT "channel", left(substr("channel", strpos("channel", '/') + 1), 1) NOT IN ('#', '&', '+', '!')
+FROM all_channels();
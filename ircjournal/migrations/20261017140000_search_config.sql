@@ -0,0 +1,89 @@
+-- Full-text search stems words in the language of each channel, eg. 'french', or not at all
+-- with 'simple', eg. for code-heavy channels. Ingestion sets it from its configuration.
+ALTER TABLE "channel"
+    ADD COLUMN "search_config" regconfig NOT NULL DEFAULT 'english';
+
+-- What full-text search looks into: the nick and text of a message, or eg. the topic.
+CREATE FUNCTION message_document(config regconfig, nick text, line text, payload text)
+    RETURNS tsvector
+AS
+$$
+SELECT to_tsvector(config, coalesce(nick, '') || ' ' || coalesce(line, payload, ''))
+$$ LANGUAGE sql IMMUTABLE;
+
+-- The searchable document of each message, in the text search configuration of its channel.
+CREATE TABLE "message_search"
+(
+    "id"       integer PRIMARY KEY NOT NULL REFERENCES "message" ON DELETE CASCADE,
+    "channel"  text                NOT NULL,
+    "document" tsvector            NOT NULL
+);
+
+INSERT INTO "message_search" ("id", "channel", "document")
+SELECT m."id", m."channel", message_document(coalesce(c."search_config", 'english'), m."nick", m."line", m."payload")
+FROM "message" m
+         LEFT JOIN "channel" c ON c."name" = m."channel"
+WHERE m."channel" IS NOT NULL;
+
+DROP INDEX "channel_line_fts";
+CREATE INDEX "message_search_fts" ON "message_search" USING gin ("channel", "document");
+
+-- Messages are indexed as they are saved, or changed.
+CREATE FUNCTION index_messages()
+    RETURNS trigger
+AS
+$$
+BEGIN
+    INSERT INTO "message_search" ("id", "channel", "document")
+    SELECT m."id", m."channel", message_document(coalesce(c."search_config", 'english'), m."nick", m."line", m."payload")
+    FROM "changed" m
+             LEFT JOIN "channel" c ON c."name" = m."channel"
+    WHERE m."channel" IS NOT NULL
+    ON CONFLICT ("id") DO UPDATE SET "channel" = excluded."channel", "document" = excluded."document";
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER "index_inserted_messages"
+    AFTER INSERT
+    ON "message"
+    REFERENCING NEW TABLE AS "changed"
+    FOR EACH STATEMENT
+EXECUTE FUNCTION index_messages();
+
+CREATE TRIGGER "index_updated_messages"
+    AFTER UPDATE
+    ON "message"
+    REFERENCING NEW TABLE AS "changed"
+    FOR EACH STATEMENT
+EXECUTE FUNCTION index_messages();
+
+-- Changing the configuration of a channel indexes its messages again.
+CREATE FUNCTION reindex_channel()
+    RETURNS trigger
+AS
+$$
+BEGIN
+    UPDATE "message_search" s
+    SET "document" = message_document(NEW."search_config", m."nick", m."line", m."payload")
+    FROM "message" m
+    WHERE m."id" = s."id"
+      AND s."channel" = NEW."name";
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+-- Messages saved before their channel are in the default configuration.
+CREATE TRIGGER "reindex_inserted_channel"
+    AFTER INSERT
+    ON "channel"
+    FOR EACH ROW
+    WHEN (NEW."search_config" <> 'english'::regconfig)
+EXECUTE FUNCTION reindex_channel();
+
+CREATE TRIGGER "reindex_updated_channel"
+    AFTER UPDATE OF "search_config"
+    ON "channel"
+    FOR EACH ROW
+    WHEN (OLD."search_config" <> NEW."search_config")
+EXECUTE FUNCTION reindex_channel();
@@ -0,0 +1,5 @@
+-- Substring and regular expression search, eg. for identifiers, URLs or hashes that full-text
+-- search splits or stems. Topics are searched too.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX "channel_line_trgm" ON "message" USING gin ("channel", coalesce("line", "payload") gin_trgm_ops);
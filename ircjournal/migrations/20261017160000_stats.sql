@@ -0,0 +1,49 @@
+-- Statistics of channels, kept up to date by ingestion as it saves messages, as counting them on
+-- every visit is slow for large channels. Hours and months are in UTC.
+
+-- How many messages, links, joins and parts each hour.
+CREATE TABLE "channel_activity"
+(
+    "channel"  text        NOT NULL,
+    "hour"     timestamptz NOT NULL,
+    "messages" integer     NOT NULL,
+    "links"    integer     NOT NULL,
+    "joins"    integer     NOT NULL,
+    "parts"    integer     NOT NULL,
+    PRIMARY KEY ("channel", "hour")
+);
+
+-- How many messages each nick said each month, and when it was first and last seen.
+CREATE TABLE "channel_nick_activity"
+(
+    "channel"    text        NOT NULL,
+    "nick"       text        NOT NULL,
+    "month"      timestamptz NOT NULL,
+    "messages"   integer     NOT NULL,
+    "first_seen" timestamptz NOT NULL,
+    "last_seen"  timestamptz NOT NULL,
+    PRIMARY KEY ("channel", "nick", "month")
+);
+
+INSERT INTO "channel_activity" ("channel", "hour", "messages", "links", "joins", "parts")
+SELECT "channel",
+       date_trunc('hour', "timestamp", 'UTC'),
+       count(*) FILTER (WHERE "opcode" IS NULL OR "opcode" = 'me'),
+       count(*) FILTER (WHERE "line" ~ 'https?://'),
+       count(*) FILTER (WHERE "opcode" = 'joined'),
+       count(*) FILTER (WHERE "opcode" IN ('left', 'quit', 'kicked'))
+FROM "message"
+WHERE "channel" IS NOT NULL
+GROUP BY 1, 2;
+
+INSERT INTO "channel_nick_activity" ("channel", "nick", "month", "messages", "first_seen", "last_seen")
+SELECT "channel",
+       "nick",
+       date_trunc('month', "timestamp", 'UTC'),
+       count(*) FILTER (WHERE "opcode" IS NULL OR "opcode" = 'me'),
+       min("timestamp"),
+       max("timestamp")
+FROM "message"
+WHERE "channel" IS NOT NULL
+  AND "nick" IS NOT NULL
+GROUP BY 1, 2, 3;
@@ -30,6 +30,80 @@ pub async fn last_message_ts(db: &Database, sc: &ServerChannel) -> Option<Dateti
     .timestamp
 }
 
+/// Records `sc` and whether it is private, unless it is known already. Also sets its text search
+/// configuration, if any, indexing its messages again if it changed.
+pub async fn save_channel(db: &Database, sc: &ServerChannel, search_config: Option<&str>) {
+    // language=sql
+    sqlx::query!(
+        r#"
+        INSERT INTO "channel" ("name", "private", "search_config")
+        VALUES ($1, $2, coalesce($3::text::regconfig, 'english'))
+        ON CONFLICT ("name") DO UPDATE SET "search_config" = excluded."search_config"
+        WHERE $3 IS NOT NULL AND "channel"."search_config" <> excluded."search_config"
+    "#,
+        sc.to_string(),
+        sc.is_query(),
+        search_config
+    )
+    .execute(db)
+    .await
+    .unwrap();
+}
+
+/// Whether PostgreSQL knows the text search configuration `name`, eg. "french".
+pub async fn search_config_exists(db: &Database, name: &str) -> bool {
+    // language=sql
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM "pg_ts_config" WHERE "cfgname" = $1) "exists!"
+    "#,
+        name
+    )
+    .fetch_one(db)
+    .await
+    .unwrap()
+}
+
+/// Adds the rows of "new_rows", messages that were just saved, to the statistics of their channel.
+/// Continues a `WITH`.
+// language=sql
+const COUNT_NEW_ROWS: &str = r#"
+    , "hourly" AS (
+        INSERT INTO "channel_activity" ("channel", "hour", "messages", "links", "joins", "parts")
+        SELECT "channel",
+               date_trunc('hour', "timestamp", 'UTC'),
+               count(*) FILTER (WHERE "opcode" IS NULL OR "opcode" = 'me'),
+               count(*) FILTER (WHERE "line" ~ 'https?://'),
+               count(*) FILTER (WHERE "opcode" = 'joined'),
+               count(*) FILTER (WHERE "opcode" IN ('left', 'quit', 'kicked'))
+        FROM "new_rows"
+        GROUP BY 1, 2
+        -- Always in the same order, so that concurrent batches do not deadlock.
+        ORDER BY 1, 2
+        ON CONFLICT ("channel", "hour") DO UPDATE
+            SET "messages" = "channel_activity"."messages" + excluded."messages",
+                "links"    = "channel_activity"."links" + excluded."links",
+                "joins"    = "channel_activity"."joins" + excluded."joins",
+                "parts"    = "channel_activity"."parts" + excluded."parts"
+    ), "per_nick" AS (
+        INSERT INTO "channel_nick_activity" ("channel", "nick", "month", "messages", "first_seen", "last_seen")
+        SELECT "channel",
+               "nick",
+               date_trunc('month', "timestamp", 'UTC'),
+               count(*) FILTER (WHERE "opcode" IS NULL OR "opcode" = 'me'),
+               min("timestamp"),
+               max("timestamp")
+        FROM "new_rows"
+        WHERE "nick" IS NOT NULL
+        GROUP BY 1, 2, 3
+        ORDER BY 1, 2, 3
+        ON CONFLICT ("channel", "nick", "month") DO UPDATE
+            SET "messages"   = "channel_nick_activity"."messages" + excluded."messages",
+                "first_seen" = least("channel_nick_activity"."first_seen", excluded."first_seen"),
+                "last_seen"  = greatest("channel_nick_activity"."last_seen", excluded."last_seen")
+    )
+"#;
+
 fn push_message_values<'a>(builder: &mut QueryBuilder<'a, Postgres>, messages: &'a [NewMessage]) {
     builder.push_values(messages, |mut b, message| {
         b /**/
@@ -39,7 +113,8 @@ fn push_message_values<'a>(builder: &mut QueryBuilder<'a, Postgres>, messages: &
             .push_bind(message.opcode.clone())
             .push_bind(message.oper_nick.clone())
             .push_bind(message.payload.clone())
-            .push_bind(message.timestamp);
+            .push_bind(message.timestamp)
+            .push_bind(message.occurrence);
     });
 }
 
@@ -59,10 +134,23 @@ pub async fn batch_insert_messages(db: &Database, messages: &[NewMessage]) -> Op
     // language=sql
     let mut builder = QueryBuilder::new(
         r#"
-        INSERT INTO message ("channel", "nick", "line", "opcode", "oper_nick", "payload", "timestamp")
+        WITH new_rows AS (
+            INSERT INTO message ("channel", "nick", "line", "opcode", "oper_nick", "payload", "timestamp", "occurrence")
         "#,
     );
     push_message_values(&mut builder, messages);
+    // Lines already saved, eg. when a log is ingested again, are skipped.
+    // language=sql
+    builder.push(
+        r#"
+            ON CONFLICT DO NOTHING
+            RETURNING *
+        )
+        "#,
+    );
+    builder.push(COUNT_NEW_ROWS);
+    // language=sql
+    builder.push(r#"SELECT FROM new_rows"#);
     execute_batch_insert_messages(builder, db).await
 }
 
@@ -74,17 +162,50 @@ pub async fn batch_insert_messages_and_notify(
     let mut builder = QueryBuilder::new(
         r#"
         WITH new_rows AS (
-            INSERT INTO message ("channel", "nick", "line", "opcode", "oper_nick", "payload", "timestamp")
+            INSERT INTO message ("channel", "nick", "line", "opcode", "oper_nick", "payload", "timestamp", "occurrence")
         "#,
     );
     push_message_values(&mut builder, messages);
     // language=sql
     builder.push(
         r#"
+            ON CONFLICT DO NOTHING
             RETURNING *
         )
-        SELECT pg_notify('new_message', row_to_json(row)::text) FROM new_rows row
         "#,
     );
+    builder.push(COUNT_NEW_ROWS);
+    // language=sql
+    builder.push(r#"SELECT pg_notify('new_message', row_to_json(row)::text) FROM new_rows row"#);
     execute_batch_insert_messages(builder, db).await
 }
+
+/// Counts the statistics of `channel` again from its messages, eg. after some were deleted.
+pub async fn recount_stats(db: &Database, channel: &str) {
+    let mut tx = db.begin().await.unwrap();
+    // language=sql
+    sqlx::query!(
+        r#"DELETE FROM "channel_activity" WHERE "channel" = $1"#,
+        channel
+    )
+    .execute(&mut tx)
+    .await
+    .unwrap();
+    // language=sql
+    sqlx::query!(
+        r#"DELETE FROM "channel_nick_activity" WHERE "channel" = $1"#,
+        channel
+    )
+    .execute(&mut tx)
+    .await
+    .unwrap();
+    // language=sql
+    let mut builder =
+        QueryBuilder::new(r#"WITH new_rows AS (SELECT * FROM message WHERE "channel" = "#);
+    builder.push_bind(channel);
+    builder.push(")");
+    builder.push(COUNT_NEW_ROWS);
+    builder.push("SELECT");
+    builder.build().execute(&mut tx).await.unwrap();
+    tx.commit().await.unwrap();
+}
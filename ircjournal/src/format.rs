@@ -0,0 +1,248 @@
+use std::path::Path;
+
+use crate::{
+    irssi::Irssi,
+    local_to_utc,
+    model::{Datetime, ServerChannel},
+    weechat::Weechat,
+    znc::Znc,
+    LineParser, Logger, ParseResult,
+};
+
+/// How many lines to look at when guessing the format of a log.
+pub const SNIFF_LINES: usize = 20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Guess from the path and first lines of the log.
+    #[default]
+    Auto,
+    Weechat,
+    Irssi,
+    Znc,
+}
+
+impl LogFormat {
+    /// Ordered from the strictest to the loosest path matching, as a tie-breaker.
+    const CONCRETE: [LogFormat; 3] = [LogFormat::Weechat, LogFormat::Znc, LogFormat::Irssi];
+
+    /// Guesses the format of the log at `path` from its first `lines`. Formats that cannot parse
+    /// `path` are never considered. Falls back to the first format accepting `path` if no line
+    /// could be parsed at all, eg. for an empty log.
+    pub fn sniff<S: AsRef<str>>(path: &Path, lines: &[S]) -> Option<LogFormat> {
+        let candidates: Vec<_> = Self::CONCRETE
+            .iter()
+            .filter_map(|&format| AnyLogger::open(format, path))
+            .map(|(_, logger)| logger)
+            .collect();
+        let scored = candidates.iter().map(|logger| {
+            let mut logger = logger.clone();
+            let score = lines
+                .iter()
+                .take(SNIFF_LINES)
+                .filter(|line| matches!(logger.parse_line(line.as_ref()), ParseResult::Ok(_)))
+                .count();
+            (logger.format(), score)
+        });
+        match scored.rev().max_by_key(|(_, score)| *score) {
+            Some((format, score)) if score > 0 => Some(format),
+            _ => candidates.first().map(AnyLogger::format),
+        }
+    }
+}
+
+fn open_as<L: Logger>(path: &Path, wrap: fn(L) -> Parser) -> Option<(ServerChannel, AnyLogger)> {
+    let logger = AnyLogger {
+        parser: wrap(L::for_path(path)),
+        timezone: chrono_tz::UTC,
+        last: None,
+    };
+    Some((L::parse_path(path)?, logger))
+}
+
+#[derive(Clone)]
+enum Parser {
+    Weechat(Weechat),
+    Irssi(Irssi),
+    Znc(Znc),
+}
+
+/// Runtime-dispatched [`Logger`], for when the format is only known from configuration.
+/// Parsers date lines as if they were logged in UTC; they are converted from the time zone of the
+/// log, see [`AnyLogger::with_timezone`].
+#[derive(Clone)]
+pub struct AnyLogger {
+    parser: Parser,
+    timezone: chrono_tz::Tz,
+    /// Timestamp of the last line, to tell the two occurrences of a repeated local time apart.
+    last: Option<Datetime>,
+}
+
+impl AnyLogger {
+    /// Returns the channel logged at `path` and a parser ready to read it, or `None` if `path` is
+    /// not valid for `format`. [`LogFormat::Auto`] must be resolved with [`LogFormat::sniff`].
+    pub fn open(format: LogFormat, path: &Path) -> Option<(ServerChannel, Self)> {
+        match format {
+            LogFormat::Auto => None,
+            LogFormat::Weechat => open_as(path, Parser::Weechat),
+            LogFormat::Irssi => open_as(path, Parser::Irssi),
+            LogFormat::Znc => open_as(path, Parser::Znc),
+        }
+    }
+
+    /// Sets the time zone the log is written in, UTC by default. See [`crate::local_to_utc`].
+    pub fn with_timezone(self, timezone: chrono_tz::Tz) -> Self {
+        Self { timezone, ..self }
+    }
+
+    pub fn timezone(&self) -> chrono_tz::Tz {
+        self.timezone
+    }
+
+    pub fn format(&self) -> LogFormat {
+        match self.parser {
+            Parser::Weechat(_) => LogFormat::Weechat,
+            Parser::Irssi(_) => LogFormat::Irssi,
+            Parser::Znc(_) => LogFormat::Znc,
+        }
+    }
+
+    /// See [`Logger::ROLLS_OVER`].
+    pub fn rolls_over(&self) -> bool {
+        match self.parser {
+            Parser::Weechat(_) => Weechat::ROLLS_OVER,
+            Parser::Irssi(_) => Irssi::ROLLS_OVER,
+            Parser::Znc(_) => Znc::ROLLS_OVER,
+        }
+    }
+
+    /// See [`Logger::continues`].
+    pub fn continues(&self, path: &Path, next: &Path) -> bool {
+        match self.parser {
+            Parser::Weechat(_) => Weechat::continues(path, next),
+            Parser::Irssi(_) => Irssi::continues(path, next),
+            Parser::Znc(_) => Znc::continues(path, next),
+        }
+    }
+}
+
+impl LineParser for AnyLogger {
+    fn is_self_dated(&self) -> bool {
+        match &self.parser {
+            Parser::Weechat(l) => l.is_self_dated(),
+            Parser::Irssi(l) => l.is_self_dated(),
+            Parser::Znc(l) => l.is_self_dated(),
+        }
+    }
+
+    fn parse_line(&mut self, line: &str) -> ParseResult {
+        let parsed = match &mut self.parser {
+            Parser::Weechat(l) => l.parse_line(line),
+            Parser::Irssi(l) => l.parse_line(line),
+            Parser::Znc(l) => l.parse_line(line),
+        };
+        match parsed {
+            ParseResult::Ok((ts, line)) => {
+                let ts = local_to_utc(&self.timezone, ts.naive_utc(), self.last.as_ref());
+                self.last = Some(ts);
+                ParseResult::Ok((ts, line))
+            }
+            parsed => parsed,
+        }
+    }
+}
+
+#[test]
+fn test_sniff() {
+    let none: &[&str] = &[];
+    assert_eq!(LogFormat::sniff(Path::new("garbage"), none), None);
+    assert_eq!(
+        LogFormat::sniff(Path::new("irc.serv.#chan.weechatlog"), none),
+        Some(LogFormat::Weechat)
+    );
+    assert_eq!(
+        LogFormat::sniff(Path::new("libera/#chan.log"), none),
+        Some(LogFormat::Irssi)
+    );
+    assert_eq!(
+        LogFormat::sniff(Path::new("libera/#chan/2019-12-14.log"), none),
+        Some(LogFormat::Znc)
+    );
+    assert_eq!(
+        LogFormat::sniff(
+            Path::new("libera/#chan.log"),
+            &[
+                "--- Log opened Sat Dec 14 23:10:02 2019",
+                "23:12 <@zopieux> test",
+            ]
+        ),
+        Some(LogFormat::Irssi)
+    );
+    assert_eq!(
+        LogFormat::sniff(
+            Path::new("libera/#chan/2019-12-14.log"),
+            &[
+                "--- Log opened Sat Dec 14 23:10:02 2019",
+                "23:12 <@zopieux> test",
+            ]
+        ),
+        Some(LogFormat::Irssi)
+    );
+    assert_eq!(
+        LogFormat::sniff(
+            Path::new("libera/#chan/2019-12-14.log"),
+            &["[23:12:14] <zopieux> test"]
+        ),
+        Some(LogFormat::Znc)
+    );
+    assert_eq!(
+        LogFormat::sniff(
+            Path::new("irc.serv.#chan.weechatlog"),
+            &["2019-12-14 23:12:14\t@zopieux\ttest"]
+        ),
+        Some(LogFormat::Weechat)
+    );
+}
+
+#[test]
+fn test_timezone() {
+    use crate::{test::ts, IrcLine};
+    let path = Path::new("irc.serv.#chan.weechatlog");
+    let (_, logger) = AnyLogger::open(LogFormat::Weechat, path).unwrap();
+    let mut logger = logger.with_timezone(chrono_tz::Europe::Paris);
+    assert_eq!(
+        logger.parse_line("2019-12-14 23:12:14\t@zopieux\ttest"),
+        ParseResult::Ok((
+            ts("2019-12-14 22:12:14"),
+            IrcLine::Message {
+                nick: "zopieux".to_string(),
+                line: "test".to_string(),
+            }
+        ))
+    );
+    assert_eq!(logger.parse_line("garbage"), ParseResult::Invalid);
+
+    // Clocks turned back at 03:00 to 02:00: the hour is logged twice, and read in order.
+    let timestamps: Vec<_> = [
+        "2021-10-31 02:30:00\tzopieux\tfirst",
+        "2021-10-31 02:59:59\tzopieux\tfirst",
+        "2021-10-31 02:30:00\tzopieux\tsecond",
+        "2021-10-31 03:00:00\tzopieux\tafter",
+    ]
+    .iter()
+    .map(|line| match logger.parse_line(line) {
+        ParseResult::Ok((ts, _)) => ts,
+        parsed => panic!("{:?}", parsed),
+    })
+    .collect();
+    assert_eq!(
+        timestamps,
+        vec![
+            ts("2021-10-31 00:30:00"),
+            ts("2021-10-31 00:59:59"),
+            ts("2021-10-31 01:30:00"),
+            ts("2021-10-31 02:00:00"),
+        ]
+    );
+}
@@ -0,0 +1,475 @@
+use lazy_static::lazy_static;
+use regex::{Match, Regex};
+use std::path::Path;
+
+use crate::{
+    model::{Datetime, ServerChannel},
+    IrcLine, LineParser, Logger, ParseResult,
+};
+
+lazy_static! {
+    static ref FNAME: Regex = Regex::new(r"^(.+)\.log$").unwrap();
+    static ref LOG_OPENED: Regex =
+        Regex::new(r"^--- Log opened (\w{3} \w{3} [0-9]{2} [0-9]{2}:[0-9]{2}:[0-9]{2} [0-9]{4})$")
+            .unwrap();
+    static ref DAY_CHANGED: Regex =
+        Regex::new(r"^--- Day changed (\w{3} \w{3} [0-9]{2} [0-9]{4})$").unwrap();
+    static ref LINE: Regex = Regex::new(r"^([0-9]{2}:[0-9]{2}(?::[0-9]{2})?) (.*)$").unwrap();
+    static ref LOG_NICK_CHANGED: Regex =
+        Regex::new(r#"^-!- [~&@%\+]*(\S+) is now known as [~&@%\+]*(\S+)$"#).unwrap();
+    static ref LOG_TOPIC_CHANGED: Regex =
+        Regex::new(r#"^-!- [~&@%\+]*(\S+) changed the topic of \S+ to: (.*)$"#).unwrap();
+    static ref LOG_JOINED: Regex =
+        Regex::new(r#"^-!- [~&@%\+]*(\S+) \[.*?\] has joined (#.+)$"#).unwrap();
+    static ref LOG_LEFT: Regex =
+        Regex::new(r#"^-!- [~&@%\+]*(\S+) \[.*?\] has left (\S+?)(?: \[(.*)\])?$"#).unwrap();
+    static ref LOG_QUIT: Regex =
+        Regex::new(r#"^-!- [~&@%\+]*(\S+) \[.*?\] has quit(?: \[(.*)\])?$"#).unwrap();
+    static ref LOG_KICKED: Regex =
+        Regex::new(r#"^-!- [~&@%\+]*(\S+) was kicked from \S+ by [~&@%\+]*(\S+)(?: \[(.*)\])?$"#)
+            .unwrap();
+    static ref LOG_MODE: Regex =
+        Regex::new(r#"^-!- mode/\S+ \[(.*?)\] by [~&@%\+]*(\S+)$"#).unwrap();
+    static ref LOG_NOTICE: Regex = Regex::new(r#"^-([^\s:!][^\s:]*)(?::\S+)?- (.*)$"#).unwrap();
+    static ref LOG_ME: Regex = Regex::new(r#"^ \* [~&@%\+]*(\S+)(?: (.*))?$"#).unwrap();
+    static ref LOG_MESSAGE: Regex = Regex::new(r#"^<[ ~&@%\+]?([^\s>]+)> ?(.*)$"#).unwrap();
+}
+
+/// irssi's default log format. Lines only carry the time of day, the date comes from the
+/// `--- Log opened` and `--- Day changed` markers that precede them.
+#[derive(Default, Clone)]
+pub struct Irssi {
+    date: Option<chrono::NaiveDate>,
+}
+
+impl Logger for Irssi {
+    fn parse_path(path: &Path) -> Option<ServerChannel> {
+        let captures = FNAME.captures(path.file_name()?.to_str()?)?;
+        let server = path.parent()?.file_name()?.to_str()?;
+        Some(ServerChannel {
+            server: server.to_string(),
+            channel: captures.get(1)?.as_str().to_string(),
+        })
+    }
+}
+
+impl Irssi {
+    /// Formats `line` the way irssi logs it in `channel`. Hosts are not saved, so they are left
+    /// empty. irssi does not log CTCP requests in channels, these lines cannot be parsed back.
+    pub fn format_line(channel: &str, time: &chrono::NaiveTime, line: &IrcLine) -> Option<String> {
+        let reason = |reason: &str| {
+            if reason.is_empty() {
+                "".to_string()
+            } else {
+                format!(" [{}]", reason)
+            }
+        };
+        let s = match line {
+            IrcLine::Garbage => return None,
+            IrcLine::NickChanged { old, new } => format!("-!- {} is now known as {}", old, new),
+            IrcLine::TopicChanged { nick, new, .. } => {
+                format!("-!- {} changed the topic of {} to: {}", nick, channel, new)
+            }
+            IrcLine::Joined { nick } => format!("-!- {} [] has joined {}", nick, channel),
+            IrcLine::Left { nick, reason: r } => {
+                format!("-!- {} [] has left {}{}", nick, channel, reason(r))
+            }
+            IrcLine::Quit { nick, reason: r } => format!("-!- {} [] has quit{}", nick, reason(r)),
+            IrcLine::Kicked {
+                oper_nick,
+                nick,
+                reason: r,
+            } => format!(
+                "-!- {} was kicked from {} by {}{}",
+                nick,
+                channel,
+                oper_nick,
+                reason(r)
+            ),
+            IrcLine::Me { nick, line } => format!(" * {} {}", nick, line),
+            IrcLine::Message { nick, line } => format!("< {}> {}", nick, line),
+            IrcLine::Notice { nick, line } => format!("-{}:{}- {}", nick, channel, line),
+            IrcLine::ModeChanged { nick, mode } => {
+                format!("-!- mode/{} [{}] by {}", channel, mode, nick)
+            }
+            IrcLine::Ctcp { nick, command } => {
+                format!("-!- {} requested CTCP {}", nick, command)
+            }
+        };
+        Some(format!("{} {}", time.format("%H:%M:%S"), s))
+    }
+
+    /// The marker dating the lines that follow it.
+    pub fn format_day_changed(date: &chrono::NaiveDate) -> String {
+        format!("--- Day changed {}", date.format("%a %b %d %Y"))
+    }
+}
+
+impl LineParser for Irssi {
+    fn is_self_dated(&self) -> bool {
+        false
+    }
+
+    fn parse_line(&mut self, line: &str) -> ParseResult {
+        let mstr = |om: Match| om.as_str().to_string();
+        let mstr_empty = |om: Option<Match>| {
+            match om {
+                Some(m) => m.as_str(),
+                _ => "",
+            }
+            .to_string()
+        };
+        if let Some(cap) = LOG_OPENED.captures(line) {
+            return match chrono::NaiveDateTime::parse_from_str(
+                cap.get(1).unwrap().as_str(),
+                "%a %b %d %H:%M:%S %Y",
+            ) {
+                Ok(t) => {
+                    self.date = Some(t.date());
+                    ParseResult::Noise
+                }
+                Err(_) => ParseResult::Invalid,
+            };
+        }
+        if let Some(cap) = DAY_CHANGED.captures(line) {
+            return match chrono::NaiveDate::parse_from_str(
+                cap.get(1).unwrap().as_str(),
+                "%a %b %d %Y",
+            ) {
+                Ok(d) => {
+                    self.date = Some(d);
+                    ParseResult::Noise
+                }
+                Err(_) => ParseResult::Invalid,
+            };
+        }
+        if line.starts_with("--- ") {
+            // Log closed, etc.
+            return ParseResult::Noise;
+        }
+        let cap = match LINE.captures(line) {
+            Some(cap) => cap,
+            None => return ParseResult::Invalid,
+        };
+        let time = cap.get(1).unwrap().as_str();
+        let time = match chrono::NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .or_else(|_| chrono::NaiveTime::parse_from_str(time, "%H:%M"))
+        {
+            Ok(t) => t,
+            Err(_) => return ParseResult::Invalid,
+        };
+        let naive = match self.date {
+            Some(date) => date.and_time(time),
+            // Cannot date this line without a previous marker.
+            None => return ParseResult::Invalid,
+        };
+        let timestamp = Datetime::from_naive_utc_and_offset(naive, chrono::Utc);
+        let s = cap.get(2).unwrap().as_str();
+        let parsed = (|| {
+            if LOG_JOINED.is_match(s) {
+                let x = LOG_JOINED.captures(s).unwrap();
+                Some(IrcLine::Joined {
+                    nick: mstr(x.get(1)?),
+                })
+            } else if LOG_LEFT.is_match(s) {
+                let x = LOG_LEFT.captures(s).unwrap();
+                Some(IrcLine::Left {
+                    nick: mstr(x.get(1)?),
+                    reason: mstr_empty(x.get(3)),
+                })
+            } else if LOG_QUIT.is_match(s) {
+                let x = LOG_QUIT.captures(s).unwrap();
+                Some(IrcLine::Quit {
+                    nick: mstr(x.get(1)?),
+                    reason: mstr_empty(x.get(2)),
+                })
+            } else if LOG_NICK_CHANGED.is_match(s) {
+                let x = LOG_NICK_CHANGED.captures(s).unwrap();
+                Some(IrcLine::NickChanged {
+                    old: mstr(x.get(1)?),
+                    new: mstr(x.get(2)?),
+                })
+            } else if LOG_TOPIC_CHANGED.is_match(s) {
+                let x = LOG_TOPIC_CHANGED.captures(s).unwrap();
+                Some(IrcLine::TopicChanged {
+                    nick: mstr(x.get(1)?),
+                    // irssi does not log the previous topic.
+                    old: "".to_string(),
+                    new: mstr(x.get(2)?),
+                })
+            } else if LOG_KICKED.is_match(s) {
+                let x = LOG_KICKED.captures(s).unwrap();
+                Some(IrcLine::Kicked {
+                    oper_nick: mstr(x.get(2)?),
+                    nick: mstr(x.get(1)?),
+                    reason: mstr_empty(x.get(3)),
+                })
+            } else if LOG_MODE.is_match(s) {
+                let x = LOG_MODE.captures(s).unwrap();
+                Some(IrcLine::ModeChanged {
+                    nick: mstr(x.get(2)?),
+                    mode: mstr(x.get(1)?),
+                })
+            } else if LOG_NOTICE.is_match(s) {
+                let x = LOG_NOTICE.captures(s).unwrap();
+                Some(IrcLine::Notice {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else if LOG_ME.is_match(s) {
+                let x = LOG_ME.captures(s).unwrap();
+                Some(IrcLine::Me {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else if LOG_MESSAGE.is_match(s) {
+                let x = LOG_MESSAGE.captures(s).unwrap();
+                Some(IrcLine::Message {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else {
+                None
+            }
+        })();
+        match parsed {
+            Some(line) => ParseResult::Ok((timestamp, line)),
+            None => ParseResult::Noise,
+        }
+    }
+}
+
+#[test]
+fn test_parse_irssi() {
+    use crate::test::ts;
+    assert_eq!(Irssi::parse_path(Path::new("garbage")), None);
+    assert_eq!(
+        Irssi::parse_path(Path::new("irclogs/libera/#bar.log")),
+        Some(ServerChannel {
+            server: "libera".to_string(),
+            channel: "#bar".to_string(),
+        })
+    );
+    assert_eq!(
+        Irssi::parse_path(Path::new("irclogs/libera/##dieses.log")),
+        Some(ServerChannel {
+            server: "libera".to_string(),
+            channel: "##dieses".to_string(),
+        })
+    );
+
+    let mut irssi = Irssi::default();
+    // No date known yet.
+    assert_eq!(
+        irssi.parse_line("23:11 < zopieux> test"),
+        ParseResult::Invalid
+    );
+    assert_eq!(
+        irssi.parse_line("--- Log opened Sat Dec 14 23:10:02 2019"),
+        ParseResult::Noise
+    );
+    assert_eq!(
+        irssi.parse_line("23:11 -!- zopieux [~zopieux@unaffiliated/zopieux] has joined ##dieses"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:11:00"),
+            IrcLine::Joined {
+                nick: "zopieux".to_string()
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("23:12:14 <@zopieux> test"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:12:14"),
+            IrcLine::Message {
+                nick: "zopieux".to_string(),
+                line: "test".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("23:13 < haileda> il pleut"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:13:00"),
+            IrcLine::Message {
+                nick: "haileda".to_string(),
+                line: "il pleut".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("--- Day changed Sun Dec 15 2019"),
+        ParseResult::Noise
+    );
+    assert_eq!(
+        irssi.parse_line("00:01  * haileda uploaded an image: (68KiB)"),
+        ParseResult::Ok((
+            ts("2019-12-15 00:01:00"),
+            IrcLine::Me {
+                nick: "haileda".to_string(),
+                line: "uploaded an image: (68KiB)".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line(
+            "00:02 -!- zopiuex [zopieux@unaffiliated/zopieux] has quit [Quit: WeeChat 2.2]"
+        ),
+        ParseResult::Ok((
+            ts("2019-12-15 00:02:00"),
+            IrcLine::Quit {
+                nick: "zopiuex".to_string(),
+                reason: "Quit: WeeChat 2.2".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line(
+            "00:03 -!- Tuxkowo [~Tuxkowo@2001:bc8:4400:2800::5d1b] has left ##dieses []"
+        ),
+        ParseResult::Ok((
+            ts("2019-12-15 00:03:00"),
+            IrcLine::Left {
+                nick: "Tuxkowo".to_string(),
+                reason: "".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("00:04 -!- Tycale [~Tycale@tycale.be] has left ##dieses [Cya]"),
+        ParseResult::Ok((
+            ts("2019-12-15 00:04:00"),
+            IrcLine::Left {
+                nick: "Tycale".to_string(),
+                reason: "Cya".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("00:05 -!- rom1504 was kicked from ##dieses by thizanne [no u]"),
+        ParseResult::Ok((
+            ts("2019-12-15 00:05:00"),
+            IrcLine::Kicked {
+                oper_nick: "thizanne".to_string(),
+                nick: "rom1504".to_string(),
+                reason: "no u".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("00:06 -!- JuanTitor is now known as ordiclic"),
+        ParseResult::Ok((
+            ts("2019-12-15 00:06:00"),
+            IrcLine::NickChanged {
+                old: "JuanTitor".to_string(),
+                new: "ordiclic".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line(
+            "00:07 -!- ChanServ changed the topic of ##dieses to: Joyeux \"anniversaire\" zopieux"
+        ),
+        ParseResult::Ok((
+            ts("2019-12-15 00:07:00"),
+            IrcLine::TopicChanged {
+                nick: "ChanServ".to_string(),
+                old: "".to_string(),
+                new: "Joyeux \"anniversaire\" zopieux".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("00:08 -!- mode/##dieses [+o zopieux] by ChanServ"),
+        ParseResult::Ok((
+            ts("2019-12-15 00:08:00"),
+            IrcLine::ModeChanged {
+                nick: "ChanServ".to_string(),
+                mode: "+o zopieux".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("00:08 -ChanServ:##dieses- Welcome!"),
+        ParseResult::Ok((
+            ts("2019-12-15 00:08:00"),
+            IrcLine::Notice {
+                nick: "ChanServ".to_string(),
+                line: "Welcome!".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        irssi.parse_line("--- Log closed Sun Dec 15 00:09:12 2019"),
+        ParseResult::Noise
+    );
+    assert_eq!(irssi.parse_line("garbage"), ParseResult::Invalid);
+}
+
+#[test]
+fn test_format_line() {
+    use crate::test::ts;
+    let s = |s: &str| s.to_string();
+    let lines = vec![
+        IrcLine::NickChanged {
+            old: s("zopieux"),
+            new: s("zop"),
+        },
+        IrcLine::TopicChanged {
+            nick: s("zopieux"),
+            old: s(""),
+            new: s("new topic"),
+        },
+        IrcLine::Joined { nick: s("Tycale") },
+        IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s(""),
+        },
+        IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s("Cya"),
+        },
+        IrcLine::Quit {
+            nick: s("haileda"),
+            reason: s("Quit: WeeChat 2.2"),
+        },
+        IrcLine::Kicked {
+            oper_nick: s("zopieux"),
+            nick: s("haileda"),
+            reason: s("no u"),
+        },
+        IrcLine::Me {
+            nick: s("haileda"),
+            line: s("waves"),
+        },
+        IrcLine::Message {
+            nick: s("haileda"),
+            line: s("il pleut"),
+        },
+        IrcLine::Notice {
+            nick: s("ChanServ"),
+            line: s("Welcome"),
+        },
+        IrcLine::ModeChanged {
+            nick: s("ChanServ"),
+            mode: s("+o zopieux"),
+        },
+    ];
+    let timestamp = ts("2020-01-25 09:31:14");
+    let mut irssi = Irssi::default();
+    assert_eq!(
+        irssi.parse_line(&Irssi::format_day_changed(&timestamp.date_naive())),
+        ParseResult::Noise
+    );
+    for line in lines {
+        let formatted = Irssi::format_line("##dieses", &timestamp.time(), &line).unwrap();
+        assert_eq!(
+            irssi.parse_line(&formatted),
+            ParseResult::Ok((timestamp, line))
+        );
+    }
+    assert_eq!(
+        Irssi::format_line("##dieses", &timestamp.time(), &IrcLine::Garbage),
+        None
+    );
+}
@@ -1,15 +1,17 @@
 extern crate lazy_static;
 
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader, SeekFrom};
 
-pub use crate::model::{Datetime, NewMessage, ServerChannel};
+pub use crate::model::{Datetime, Message, NewMessage, ServerChannel};
 pub type Database = sqlx::postgres::PgPool;
-pub type MessageEvent = (ServerChannel, String);
 
 pub mod db;
+pub mod format;
+pub mod irssi;
 pub mod model;
 pub mod weechat;
+pub mod znc;
 
 #[derive(PartialEq, Debug)]
 pub enum IrcLine {
@@ -47,6 +49,18 @@ pub enum IrcLine {
         nick: String,
         line: String,
     },
+    Notice {
+        nick: String,
+        line: String,
+    },
+    ModeChanged {
+        nick: String,
+        mode: String,
+    },
+    Ctcp {
+        nick: String,
+        command: String,
+    },
 }
 
 pub fn line_to_new_message(
@@ -62,6 +76,7 @@ pub fn line_to_new_message(
         nick: None,
         oper_nick: None,
         line: None,
+        occurrence: 0,
     };
     match line {
         IrcLine::NickChanged { old, new } => Some(NewMessage {
@@ -119,27 +134,133 @@ pub fn line_to_new_message(
             line: Some(line),
             ..m
         }),
+        IrcLine::Notice { nick, line } => Some(NewMessage {
+            nick: Some(nick),
+            line: Some(line),
+            opcode: Some("notice".to_owned()),
+            ..m
+        }),
+        IrcLine::ModeChanged { nick, mode } => Some(NewMessage {
+            nick: Some(nick),
+            payload: Some(mode),
+            opcode: Some("mode".to_owned()),
+            ..m
+        }),
+        IrcLine::Ctcp { nick, command } => Some(NewMessage {
+            nick: Some(nick),
+            payload: Some(command),
+            opcode: Some("ctcp".to_owned()),
+            ..m
+        }),
         IrcLine::Garbage => None,
     }
 }
 
-async fn find_last_line<L: Logger, F>(
-    line: &mut String,
-    reader: &mut BufReader<F>,
-    end: u64,
-) -> Option<String>
+/// The reverse of [`line_to_new_message`]. The previous topic is not saved, so it is empty.
+pub fn message_to_line(message: &Message) -> IrcLine {
+    let s = |field: &Option<String>| field.clone().unwrap_or_default();
+    let nick = s(&message.nick);
+    match message.opcode.as_deref() {
+        None => IrcLine::Message {
+            nick,
+            line: s(&message.line),
+        },
+        Some("nick") => IrcLine::NickChanged {
+            old: nick,
+            new: s(&message.payload),
+        },
+        Some("topic") => IrcLine::TopicChanged {
+            nick,
+            old: "".to_string(),
+            new: s(&message.payload),
+        },
+        Some("joined") => IrcLine::Joined { nick },
+        Some("left") => IrcLine::Left {
+            nick,
+            reason: s(&message.payload),
+        },
+        Some("quit") => IrcLine::Quit {
+            nick,
+            reason: s(&message.payload),
+        },
+        Some("kicked") => IrcLine::Kicked {
+            oper_nick: s(&message.oper_nick),
+            nick,
+            reason: s(&message.payload),
+        },
+        Some("me") => IrcLine::Me {
+            nick,
+            line: s(&message.line),
+        },
+        Some("notice") => IrcLine::Notice {
+            nick,
+            line: s(&message.line),
+        },
+        Some("mode") => IrcLine::ModeChanged {
+            nick,
+            mode: s(&message.payload),
+        },
+        Some("ctcp") => IrcLine::Ctcp {
+            nick,
+            command: s(&message.payload),
+        },
+        Some(_) => IrcLine::Garbage,
+    }
+}
+
+type Fingerprint = (
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+/// Numbers identical messages sharing a timestamp, as logs only have a one-second resolution.
+/// Fed the messages of a log in order, it numbers them the same way every time, so that ingesting
+/// a log twice yields the same rows.
+#[derive(Default, Debug)]
+pub struct Occurrences {
+    timestamp: Option<Datetime>,
+    seen: HashMap<Fingerprint, i16>,
+}
+
+impl Occurrences {
+    pub fn number(&mut self, mut message: NewMessage) -> NewMessage {
+        if self.timestamp != Some(message.timestamp) {
+            self.timestamp = Some(message.timestamp);
+            self.seen.clear();
+        }
+        let count = self
+            .seen
+            .entry((
+                message.channel.clone(),
+                message.nick.clone(),
+                message.opcode.clone(),
+                message.line.clone(),
+            ))
+            .or_default();
+        message.occurrence = *count;
+        *count = count.saturating_add(1);
+        message
+    }
+}
+
+async fn find_last_line<F>(line: &mut String, reader: &mut BufReader<F>, end: u64) -> Option<String>
 where
     F: AsyncRead + AsyncSeekExt + Unpin,
 {
     let mut offset = 64u64;
     loop {
         line.clear();
-        reader.seek(SeekFrom::Start(end - offset)).await.ok()?;
+        let start = end.saturating_sub(offset);
+        reader.seek(SeekFrom::Start(start)).await.ok()?;
         match reader.read_to_string(line).await {
             Ok(0) => return None,
             Ok(_) => {
                 let lasts: Vec<_> = line[0..line.len() - 1].rsplitn(2, '\n').collect();
                 match lasts.len() {
+                    // The file is a single line.
+                    1 if start == 0 => return Some(lasts[0].to_string()),
                     1 => offset *= 2,
                     2 => return Some(lasts[0].to_string()),
                     _ => unreachable!(),
@@ -150,13 +271,17 @@ where
     }
 }
 
-pub async fn seek_past_line<L: Logger, F>(
+pub async fn seek_past_line<P: LineParser, F>(
+    logger: &mut P,
     reader: &mut BufReader<F>,
     needle: &Datetime,
 ) -> Option<DatedIrcLine>
 where
     F: AsyncRead + AsyncSeekExt + Unpin,
 {
+    if !logger.is_self_dated() {
+        return scan_past_line(logger, reader, needle).await;
+    }
     let mut line = String::new();
     let mut start: u64 = 0;
     let mut end: u64 = reader.seek(SeekFrom::End(0)).await.ok()?;
@@ -167,8 +292,8 @@ where
         let dated_line = loop {
             line.clear();
             let parsed = match reader.read_line(&mut line).await {
-                Ok(0) => L::parse_line(&find_last_line::<L, F>(&mut line, reader, end).await?),
-                Ok(_) => L::parse_line(&line[0..line.len() - 1]),
+                Ok(0) => logger.parse_line(&find_last_line(&mut line, reader, end).await?),
+                Ok(_) => logger.parse_line(&line[0..line.len() - 1]),
                 Err(_) => ParseResult::Invalid,
             };
             match (parsed, attempts) {
@@ -191,7 +316,7 @@ where
                     line.clear();
                     let parsed = match reader.read_line(&mut line).await {
                         Ok(0) => break,
-                        Ok(_) => L::parse_line(&line[0..line.len() - 1]),
+                        Ok(_) => logger.parse_line(&line[0..line.len() - 1]),
                         Err(_) => break,
                     };
                     match parsed {
@@ -215,8 +340,91 @@ where
     None
 }
 
+/// Linear equivalent of [`seek_past_line`], for parsers whose lines cannot be dated on their own.
+/// Unlike [`seek_past_line`], the reader is always left past the last line dated at or before
+/// `needle`, even if none matches it exactly. `logger` is left in the state it had at that point.
+pub async fn scan_past_line<P: LineParser, F>(
+    logger: &mut P,
+    reader: &mut BufReader<F>,
+    needle: &Datetime,
+) -> Option<DatedIrcLine>
+where
+    F: AsyncRead + AsyncSeekExt + Unpin,
+{
+    let mut line = String::new();
+    let mut pos = reader.seek(SeekFrom::Start(0)).await.ok()?;
+    let mut past = (pos, logger.clone(), None);
+    loop {
+        line.clear();
+        match reader.read_line(&mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => pos += n as u64,
+        }
+        match logger.parse_line(line.trim_end_matches('\n')) {
+            ParseResult::Ok((ts, _)) if &ts > needle => break,
+            ParseResult::Ok(dated_line) => past = (pos, logger.clone(), Some(dated_line)),
+            _ => {}
+        }
+    }
+    let (pos, state, dated_line) = past;
+    *logger = state;
+    reader.seek(SeekFrom::Start(pos)).await.ok()?;
+    dated_line.filter(|(ts, _)| ts == needle)
+}
+
+/// Moves `reader` to the end of the file, keeping the `logger` state in sync.
+pub async fn seek_to_end<P: LineParser, F>(
+    logger: &mut P,
+    reader: &mut BufReader<F>,
+) -> std::io::Result<u64>
+where
+    F: AsyncRead + AsyncSeekExt + Unpin,
+{
+    if logger.is_self_dated() {
+        return reader.seek(SeekFrom::End(0)).await;
+    }
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? != 0 {
+        logger.parse_line(line.trim_end_matches('\n'));
+        line.clear();
+    }
+    reader.seek(SeekFrom::Current(0)).await
+}
+
 type DatedIrcLine = (Datetime, IrcLine);
 
+/// Converts `naive`, a local time in `timezone`, to UTC. Times skipped when clocks are turned
+/// forward resolve to the instant of the change. Times repeated when clocks are turned back
+/// resolve to their first occurrence, unless it is earlier than `not_before`: logs are read in
+/// order, so once past the first occurrence, eg. `not_before` is the previous line, the repeated
+/// hour is its second occurrence. This keeps the timestamps of a log increasing, except for lines
+/// of the second occurrence that are still later than the last line of the first one.
+pub fn local_to_utc(
+    timezone: &chrono_tz::Tz,
+    naive: chrono::NaiveDateTime,
+    not_before: Option<&Datetime>,
+) -> Datetime {
+    use chrono::{LocalResult, TimeZone, Timelike};
+    let mut local = naive;
+    loop {
+        match timezone.from_local_datetime(&local) {
+            LocalResult::Single(t) => return t.with_timezone(&chrono::Utc),
+            LocalResult::Ambiguous(first, second) => {
+                let first = first.with_timezone(&chrono::Utc);
+                return match not_before {
+                    Some(not_before) if &first < not_before => second.with_timezone(&chrono::Utc),
+                    _ => first,
+                };
+            }
+            // In a gap, look for the first minute past it.
+            LocalResult::None => {
+                local = local.with_second(0).unwrap().with_nanosecond(0).unwrap()
+                    + chrono::Duration::minutes(1)
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ParseResult {
     Invalid,
@@ -224,16 +432,41 @@ pub enum ParseResult {
     Ok(DatedIrcLine),
 }
 
-pub trait Logger {
+pub trait LineParser: Clone {
+    /// Whether each line carries its full timestamp, so that any line can be parsed in isolation.
+    /// Parsers that derive dates from previous lines (eg. day markers) must return false.
+    fn is_self_dated(&self) -> bool {
+        true
+    }
+
+    fn parse_line(&mut self, line: &str) -> ParseResult;
+}
+
+pub trait Logger: LineParser + Default {
+    /// Whether the log is split in one file per period (eg. per day), see [`Logger::continues`].
+    const ROLLS_OVER: bool = false;
+
     fn parse_path(path: &Path) -> Option<ServerChannel>;
-    fn parse_line(line: &str) -> ParseResult;
+
+    /// Returns a parser for the log at `path`, for formats that take some state from it.
+    fn for_path(_path: &Path) -> Self {
+        Self::default()
+    }
+
+    /// Whether `next` is a later file of the same log as `path`.
+    fn continues(_path: &Path, _next: &Path) -> bool {
+        false
+    }
 }
 
 #[cfg(test)]
 mod test {
     use tempfile::tempdir;
 
-    use crate::{model::Datetime, seek_past_line, weechat::Weechat, IrcLine};
+    use crate::{
+        irssi::Irssi, line_to_new_message, local_to_utc, message_to_line, model::Datetime,
+        seek_past_line, weechat::Weechat, IrcLine, LineParser, Message, Occurrences, ServerChannel,
+    };
 
     pub(crate) fn ts(x: &'static str) -> Datetime {
         chrono::DateTime::parse_from_rfc3339(&format!("{}+00:00", x.replace(" ", "T")))
@@ -241,6 +474,76 @@ mod test {
             .with_timezone(&chrono::Utc)
     }
 
+    #[test]
+    fn test_occurrences() {
+        let sc = ServerChannel::new("libera", "#chan");
+        let mut occurrences = Occurrences::default();
+        let mut number = |ts_: &'static str, line: &str| {
+            let line = IrcLine::Message {
+                nick: "haileda".to_owned(),
+                line: line.to_owned(),
+            };
+            occurrences
+                .number(line_to_new_message(line, &sc, ts(ts_)).unwrap())
+                .occurrence
+        };
+        assert_eq!(number("2020-01-25 09:31:14", "+1"), 0);
+        assert_eq!(number("2020-01-25 09:31:14", "+1"), 1);
+        assert_eq!(number("2020-01-25 09:31:14", "-1"), 0);
+        assert_eq!(number("2020-01-25 09:31:14", "+1"), 2);
+        assert_eq!(number("2020-01-25 09:31:15", "+1"), 0);
+    }
+
+    #[test]
+    fn test_message_to_line() {
+        let sc = ServerChannel::new("libera", "#chan");
+        let s = |s: &str| s.to_string();
+        let lines = vec![
+            IrcLine::Message {
+                nick: s("haileda"),
+                line: s("il pleut"),
+            },
+            IrcLine::Kicked {
+                oper_nick: s("zopieux"),
+                nick: s("haileda"),
+                reason: s("no u"),
+            },
+            IrcLine::NickChanged {
+                old: s("zopieux"),
+                new: s("zop"),
+            },
+            IrcLine::Ctcp {
+                nick: s("haileda"),
+                command: s("VERSION"),
+            },
+        ];
+        for line in lines {
+            let m = line_to_new_message(line, &sc, ts("2020-01-25 09:31:14")).unwrap();
+            let message = Message {
+                id: 1,
+                channel: m.channel.clone(),
+                nick: m.nick.clone(),
+                line: m.line.clone(),
+                opcode: m.opcode.clone(),
+                oper_nick: m.oper_nick.clone(),
+                payload: m.payload.clone(),
+                timestamp: m.timestamp,
+                occurrence: m.occurrence,
+            };
+            let back = line_to_new_message(message_to_line(&message), &sc, m.timestamp).unwrap();
+            assert_eq!(
+                (
+                    back.nick,
+                    back.line,
+                    back.opcode,
+                    back.oper_nick,
+                    back.payload
+                ),
+                (m.nick, m.line, m.opcode, m.oper_nick, m.payload)
+            );
+        }
+    }
+
     #[tokio::test]
     async fn test_seek_paste_line_weechat() {
         use tokio::io::AsyncWriteExt;
@@ -248,7 +551,7 @@ mod test {
         let fname = dir.path().join("server.#chan.weechatlogs");
         {
             let mut f = tokio::fs::File::create(&fname).await.unwrap();
-            f.write(
+            f.write_all(
                 "2020-01-25 09:31:14\thaileda\til pleut
 2020-01-25 09:31:18\thaileda\til mouille
 2020-01-25 09:31:34\thaileda\ty'a une houle de 5m
@@ -268,7 +571,7 @@ mod test {
                 let f = tokio::fs::File::open(&$fname).await.unwrap();
                 let mut buff = tokio::io::BufReader::new(f);
                 assert_eq!(
-                    seek_past_line::<Weechat, _>(&mut buff, &ts($ts)).await,
+                    seek_past_line(&mut Weechat, &mut buff, &ts($ts)).await,
                     $res
                 );
             };
@@ -314,4 +617,135 @@ mod test {
             }
         );
     }
+
+    #[tokio::test]
+    async fn test_seek_past_line_irssi() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+        let dir = tempdir().unwrap();
+        let fname = dir.path().join("#chan.log");
+        {
+            let mut f = tokio::fs::File::create(&fname).await.unwrap();
+            f.write_all(
+                "--- Log opened Sat Jan 25 09:30:00 2020
+09:31 < haileda> il pleut
+09:31 < haileda> il mouille
+09:39 < Dettorer> ça rime pas
+--- Day changed Sun Jan 26 2020
+09:39 < haileda> c'est un haiku
+10:02 < spider-mario> Dettorer: non\n"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        };
+
+        let f = tokio::fs::File::open(&fname).await.unwrap();
+        let mut buff = tokio::io::BufReader::new(f);
+        let mut irssi = Irssi::default();
+        assert_eq!(
+            seek_past_line(&mut irssi, &mut buff, &ts("2020-01-25 09:40:00")).await,
+            None
+        );
+        assert_eq!(
+            seek_past_line(&mut irssi, &mut buff, &ts("2020-01-25 09:31:00")).await,
+            Some((
+                ts("2020-01-25 09:31:00"),
+                IrcLine::Message {
+                    nick: "haileda".to_string(),
+                    line: "il mouille".to_string()
+                }
+            ))
+        );
+        assert_eq!(
+            seek_past_line(&mut irssi, &mut buff, &ts("2020-01-25 09:39:00")).await,
+            Some((
+                ts("2020-01-25 09:39:00"),
+                IrcLine::Message {
+                    nick: "Dettorer".to_string(),
+                    line: "ça rime pas".to_string()
+                }
+            ))
+        );
+        // The logger must resume with the right date.
+        let mut line = String::new();
+        buff.read_line(&mut line).await.unwrap();
+        assert_eq!(irssi.parse_line(line.trim_end()), crate::ParseResult::Noise);
+        line.clear();
+        buff.read_line(&mut line).await.unwrap();
+        assert_eq!(
+            irssi.parse_line(line.trim_end()),
+            crate::ParseResult::Ok((
+                ts("2020-01-26 09:39:00"),
+                IrcLine::Message {
+                    nick: "haileda".to_string(),
+                    line: "c'est un haiku".to_string()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_local_to_utc() {
+        let tz = chrono_tz::America::New_York;
+        let local = |x| chrono::NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(
+            local_to_utc(&tz, local("2021-06-01 12:00:00"), None),
+            ts("2021-06-01 16:00:00")
+        );
+        assert_eq!(
+            local_to_utc(&chrono_tz::UTC, local("2021-06-01 12:00:00"), None),
+            ts("2021-06-01 12:00:00")
+        );
+        // Clocks turned forward at 02:00 to 03:00.
+        assert_eq!(
+            local_to_utc(&tz, local("2021-03-14 01:59:59"), None),
+            ts("2021-03-14 06:59:59")
+        );
+        assert_eq!(
+            local_to_utc(&tz, local("2021-03-14 02:30:10"), None),
+            ts("2021-03-14 07:00:00")
+        );
+        assert_eq!(
+            local_to_utc(&tz, local("2021-03-14 03:00:00"), None),
+            ts("2021-03-14 07:00:00")
+        );
+        // Clocks turned back at 02:00 to 01:00.
+        assert_eq!(
+            local_to_utc(&tz, local("2021-11-07 01:30:00"), None),
+            ts("2021-11-07 05:30:00")
+        );
+        assert_eq!(
+            local_to_utc(
+                &tz,
+                local("2021-11-07 01:30:00"),
+                Some(&ts("2021-11-07 05:00:00"))
+            ),
+            ts("2021-11-07 05:30:00")
+        );
+        // Past the first 01:59:59, 01:30:00 is the second one.
+        assert_eq!(
+            local_to_utc(
+                &tz,
+                local("2021-11-07 01:30:00"),
+                Some(&ts("2021-11-07 05:59:59"))
+            ),
+            ts("2021-11-07 06:30:00")
+        );
+        assert_eq!(
+            local_to_utc(
+                &tz,
+                local("2021-11-07 01:59:59"),
+                Some(&ts("2021-11-07 06:30:00"))
+            ),
+            ts("2021-11-07 06:59:59")
+        );
+        assert_eq!(
+            local_to_utc(
+                &tz,
+                local("2021-11-07 02:00:00"),
+                Some(&ts("2021-11-07 06:59:59"))
+            ),
+            ts("2021-11-07 07:00:00")
+        );
+    }
 }
@@ -12,7 +12,7 @@ pub struct ServerChannel {
     pub channel: String,
 }
 
-#[derive(PartialEq, Debug, serde::Deserialize, sqlx::Type)]
+#[derive(PartialEq, Debug, serde::Deserialize, serde::Serialize, sqlx::Type)]
 pub struct Message {
     pub id: i32,
     pub channel: Option<String>,
@@ -22,6 +22,7 @@ pub struct Message {
     pub oper_nick: Option<String>,
     pub payload: Option<String>,
     pub timestamp: Datetime,
+    pub occurrence: i16,
 }
 
 #[derive(Debug, sqlx::Type)]
@@ -33,6 +34,8 @@ pub struct NewMessage {
     pub oper_nick: Option<String>,
     pub payload: Option<String>,
     pub timestamp: Datetime,
+    /// Tells identical messages with the same timestamp apart.
+    pub occurrence: i16,
 }
 
 fn encode_hash(s: &str) -> String {
@@ -50,6 +53,12 @@ impl ServerChannel {
             channel: channel.to_string(),
         }
     }
+
+    /// Whether this is a private conversation with a user rather than a channel, as channel names
+    /// start with a channel type prefix.
+    pub fn is_query(&self) -> bool {
+        !self.channel.starts_with(['#', '&', '+', '!'])
+    }
 }
 
 impl std::fmt::Display for ServerChannel {
@@ -67,6 +76,13 @@ impl std::str::FromStr for ServerChannel {
     }
 }
 
+/// Serialized as displayed, eg. `libera/#chan`.
+impl serde::Serialize for ServerChannel {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl UriDisplay<Path> for ServerChannel {
     fn fmt(&self, f: &mut Formatter<'_, Path>) -> std::fmt::Result {
         f.write_value(format!(
@@ -98,3 +114,11 @@ impl<'r> FromUriParam<Path, &'r ServerChannel> for ServerChannel {
         param
     }
 }
+
+#[test]
+fn test_is_query() {
+    assert!(!ServerChannel::new("libera", "#chan").is_query());
+    assert!(!ServerChannel::new("libera", "##chan").is_query());
+    assert!(!ServerChannel::new("libera", "&local").is_query());
+    assert!(ServerChannel::new("libera", "zopieux").is_query());
+}
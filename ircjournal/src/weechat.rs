@@ -4,7 +4,7 @@ use std::path::Path;
 
 use crate::{
     model::{Datetime, ServerChannel},
-    IrcLine, Logger, ParseResult,
+    IrcLine, LineParser, Logger, ParseResult,
 };
 
 lazy_static! {
@@ -24,10 +24,17 @@ lazy_static! {
         Regex::new(r#"^<--\t[~&@%\+]*(\S+) \(.*?\) has quit(?: \((.*?)\))?$"#).unwrap();
     static ref LOG_KICKED: Regex =
         Regex::new(r#"^<--\t[~&@%\+]*(\S+) has kicked [~&@%\+]*(\S+)(?: \((.*?)\))?$"#).unwrap();
+    static ref LOG_NOTICE: Regex =
+        Regex::new(r#"^--\tNotice\([~&@%\+]*(\S+?)\)(?: -> \S+)?: (.*)$"#).unwrap();
+    static ref LOG_MODE: Regex =
+        Regex::new(r#"^--\tMode \S+ \[(.*?)\] by [~&@%\+]*(\S+)$"#).unwrap();
+    static ref LOG_CTCP: Regex =
+        Regex::new(r#"^--\tCTCP requested by [~&@%\+]*(\S+): (.*)$"#).unwrap();
     static ref LOG_ME: Regex = Regex::new(r#"^ \*\t[~&@%\+]*(\S+)(?: (.*))?$"#).unwrap();
     static ref LOG_MESSAGE: Regex = Regex::new(r#"^[~&@%\+]*([^\s<-]\S*)\t(.*)$"#).unwrap();
 }
 
+#[derive(Default, Clone)]
 pub struct Weechat;
 
 impl Logger for Weechat {
@@ -38,8 +45,68 @@ impl Logger for Weechat {
             channel: captures.get(2)?.as_str().to_string(),
         })
     }
+}
+
+impl Weechat {
+    /// Formats `line` the way WeeChat logs it in `channel`, so that it can be parsed back. Hosts
+    /// are not saved, so they are left empty.
+    pub fn format_line(
+        channel: &str,
+        timestamp: &chrono::NaiveDateTime,
+        line: &IrcLine,
+    ) -> Option<String> {
+        let reason = |reason: &str, open: &str, close: &str| {
+            if reason.is_empty() {
+                "".to_string()
+            } else {
+                format!(" {}{}{}", open, reason, close)
+            }
+        };
+        let s = match line {
+            IrcLine::Garbage => return None,
+            IrcLine::NickChanged { old, new } => format!("--\t{} is now known as {}", old, new),
+            IrcLine::TopicChanged { nick, old, new } => format!(
+                "--\t{} has changed topic for {} from \"{}\" to \"{}\"",
+                nick, channel, old, new
+            ),
+            IrcLine::Joined { nick } => format!("-->\t{} () has joined {}", nick, channel),
+            IrcLine::Left { nick, reason: r } => format!(
+                "<--\t{} () has left {}{}",
+                nick,
+                channel,
+                reason(r, "(\"", "\")")
+            ),
+            IrcLine::Quit { nick, reason: r } => {
+                format!("<--\t{} () has quit{}", nick, reason(r, "(", ")"))
+            }
+            IrcLine::Kicked {
+                oper_nick,
+                nick,
+                reason: r,
+            } => format!(
+                "<--\t{} has kicked {}{}",
+                oper_nick,
+                nick,
+                reason(r, "(", ")")
+            ),
+            IrcLine::Me { nick, line } => format!(" *\t{} {}", nick, line),
+            IrcLine::Message { nick, line } => format!("{}\t{}", nick, line),
+            IrcLine::Notice { nick, line } => {
+                format!("--\tNotice({}) -> {}: {}", nick, channel, line)
+            }
+            IrcLine::ModeChanged { nick, mode } => {
+                format!("--\tMode {} [{}] by {}", channel, mode, nick)
+            }
+            IrcLine::Ctcp { nick, command } => {
+                format!("--\tCTCP requested by {}: {}", nick, command)
+            }
+        };
+        Some(format!("{}\t{}", timestamp.format("%Y-%m-%d %H:%M:%S"), s))
+    }
+}
 
-    fn parse_line(line: &str) -> ParseResult {
+impl LineParser for Weechat {
+    fn parse_line(&mut self, line: &str) -> ParseResult {
         let mstr = |om: Match| om.as_str().to_string();
         let mstr_empty = |om: Option<Match>| {
             match om {
@@ -99,6 +166,24 @@ impl Logger for Weechat {
                     nick: mstr(x.get(2)?),
                     reason: mstr_empty(x.get(3)),
                 })
+            } else if LOG_NOTICE.is_match(s) {
+                let x = LOG_NOTICE.captures(s).unwrap();
+                Some(IrcLine::Notice {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else if LOG_MODE.is_match(s) {
+                let x = LOG_MODE.captures(s).unwrap();
+                Some(IrcLine::ModeChanged {
+                    nick: mstr(x.get(2)?),
+                    mode: mstr(x.get(1)?),
+                })
+            } else if LOG_CTCP.is_match(s) {
+                let x = LOG_CTCP.captures(s).unwrap();
+                Some(IrcLine::Ctcp {
+                    nick: mstr(x.get(1)?),
+                    command: mstr(x.get(2)?),
+                })
             } else if LOG_ME.is_match(s) {
                 let x = LOG_ME.captures(s).unwrap();
                 Some(IrcLine::Me {
@@ -141,7 +226,7 @@ fn test_parse_name() {
         })
     );
     assert_eq!(
-        Weechat::parse_line(
+        Weechat.parse_line(
             "2019-12-14 23:11:17\t-->\tzopieux (~zopieux@unaffiliated/zopieux) has joined ##dieses"
         ),
         ParseResult::Ok((
@@ -151,14 +236,14 @@ fn test_parse_name() {
             }
         ))
     );
-    assert_eq!(Weechat::parse_line("2019-12-14 23:11:38\t<--\tzopiuex (zopieux@unaffiliated/zopieux) has quit (Quit: WeeChat 2.2)"),
+    assert_eq!(Weechat.parse_line("2019-12-14 23:11:38\t<--\tzopiuex (zopieux@unaffiliated/zopieux) has quit (Quit: WeeChat 2.2)"),
                ParseResult::Ok((ts("2019-12-14 23:11:38"),
                      IrcLine::Quit { nick: "zopiuex".to_string(), reason: "Quit: WeeChat 2.2".to_string() })));
-    assert_eq!(Weechat::parse_line("2019-12-16 15:51:46\t<--\tTuxkowo (~Tuxkowo@2001:bc8:4400:2800::5d1b) has left ##dieses"),
+    assert_eq!(Weechat.parse_line("2019-12-16 15:51:46\t<--\tTuxkowo (~Tuxkowo@2001:bc8:4400:2800::5d1b) has left ##dieses"),
                ParseResult::Ok((ts("2019-12-16 15:51:46"),
                      IrcLine::Left { nick: "Tuxkowo".to_string(), reason: "".to_string() })));
     assert_eq!(
-        Weechat::parse_line(
+        Weechat.parse_line(
             "2019-12-31 14:14:18\t<--\tTycale (~Tycale@tycale.be) has left ##dieses (\"Cya\")"
         ),
         ParseResult::Ok((
@@ -170,7 +255,7 @@ fn test_parse_name() {
         ))
     );
     assert_eq!(
-        Weechat::parse_line("2021-04-29 18:46:41\t *\thaileda uploaded an image: (68KiB)"),
+        Weechat.parse_line("2021-04-29 18:46:41\t *\thaileda uploaded an image: (68KiB)"),
         ParseResult::Ok((
             ts("2021-04-29 18:46:41"),
             IrcLine::Me {
@@ -180,7 +265,7 @@ fn test_parse_name() {
         ))
     );
     assert_eq!(
-        Weechat::parse_line("2019-12-14 23:12:14\t@zopieux\ttest"),
+        Weechat.parse_line("2019-12-14 23:12:14\t@zopieux\ttest"),
         ParseResult::Ok((
             ts("2019-12-14 23:12:14"),
             IrcLine::Message {
@@ -189,11 +274,11 @@ fn test_parse_name() {
             }
         ))
     );
-    assert_eq!(Weechat::parse_line("2021-04-26 20:09:33\t--\tChanServ has changed topic for ##dieses from \"Bienvenue \"sur ##dieses\" to \"Joyeux \"anniversaire\" zopieux\""),
+    assert_eq!(Weechat.parse_line("2021-04-26 20:09:33\t--\tChanServ has changed topic for ##dieses from \"Bienvenue \"sur ##dieses\" to \"Joyeux \"anniversaire\" zopieux\""),
                ParseResult::Ok((ts("2021-04-26 20:09:33"),
                      IrcLine::TopicChanged { nick: "ChanServ".to_string(), old: "Bienvenue \"sur ##dieses".to_string(), new: "Joyeux \"anniversaire\" zopieux".to_string() })));
     assert_eq!(
-        Weechat::parse_line("2021-01-19 00:39:46\t<--\tthizanne has kicked rom1504"),
+        Weechat.parse_line("2021-01-19 00:39:46\t<--\tthizanne has kicked rom1504"),
         ParseResult::Ok((
             ts("2021-01-19 00:39:46"),
             IrcLine::Kicked {
@@ -204,7 +289,7 @@ fn test_parse_name() {
         ))
     );
     assert_eq!(
-        Weechat::parse_line("2021-01-19 00:39:46\t<--\tthizanne has kicked rom1504 (no u)"),
+        Weechat.parse_line("2021-01-19 00:39:46\t<--\tthizanne has kicked rom1504 (no u)"),
         ParseResult::Ok((
             ts("2021-01-19 00:39:46"),
             IrcLine::Kicked {
@@ -215,7 +300,37 @@ fn test_parse_name() {
         ))
     );
     assert_eq!(
-        Weechat::parse_line("2021-01-19 12:59:06\t--\tJuanTitor is now known as ordiclic"),
+        Weechat.parse_line("2021-01-19 12:58:01\t--\tNotice(ChanServ) -> ##dieses: Welcome!"),
+        ParseResult::Ok((
+            ts("2021-01-19 12:58:01"),
+            IrcLine::Notice {
+                nick: "ChanServ".to_string(),
+                line: "Welcome!".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        Weechat.parse_line("2021-01-19 12:58:02\t--\tMode ##dieses [+o thizanne] by @zopieux"),
+        ParseResult::Ok((
+            ts("2021-01-19 12:58:02"),
+            IrcLine::ModeChanged {
+                nick: "zopieux".to_string(),
+                mode: "+o thizanne".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        Weechat.parse_line("2021-01-19 12:58:03\t--\tCTCP requested by haileda: VERSION"),
+        ParseResult::Ok((
+            ts("2021-01-19 12:58:03"),
+            IrcLine::Ctcp {
+                nick: "haileda".to_string(),
+                command: "VERSION".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        Weechat.parse_line("2021-01-19 12:59:06\t--\tJuanTitor is now known as ordiclic"),
         ParseResult::Ok((
             ts("2021-01-19 12:59:06"),
             IrcLine::NickChanged {
@@ -225,3 +340,70 @@ fn test_parse_name() {
         ))
     );
 }
+
+#[test]
+fn test_format_line() {
+    use crate::test::ts;
+    let s = |s: &str| s.to_string();
+    let lines = vec![
+        IrcLine::NickChanged {
+            old: s("zopieux"),
+            new: s("zop"),
+        },
+        IrcLine::TopicChanged {
+            nick: s("zopieux"),
+            old: s("old topic"),
+            new: s("new topic"),
+        },
+        IrcLine::Joined { nick: s("Tycale") },
+        IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s(""),
+        },
+        IrcLine::Left {
+            nick: s("Tycale"),
+            reason: s("Cya"),
+        },
+        IrcLine::Quit {
+            nick: s("haileda"),
+            reason: s("Quit: WeeChat 2.2"),
+        },
+        IrcLine::Kicked {
+            oper_nick: s("zopieux"),
+            nick: s("haileda"),
+            reason: s("no u"),
+        },
+        IrcLine::Me {
+            nick: s("haileda"),
+            line: s("waves"),
+        },
+        IrcLine::Message {
+            nick: s("haileda"),
+            line: s("il pleut"),
+        },
+        IrcLine::Notice {
+            nick: s("ChanServ"),
+            line: s("Welcome"),
+        },
+        IrcLine::ModeChanged {
+            nick: s("ChanServ"),
+            mode: s("+o zopieux"),
+        },
+        IrcLine::Ctcp {
+            nick: s("haileda"),
+            command: s("VERSION"),
+        },
+    ];
+    let timestamp = ts("2020-01-25 09:31:14");
+    for line in lines {
+        let formatted = Weechat::format_line("##dieses", &timestamp.naive_utc(), &line).unwrap();
+        assert_eq!(
+            Weechat.parse_line(&formatted),
+            ParseResult::Ok((timestamp, line))
+        );
+    }
+    assert_eq!(
+        Weechat::format_line("##dieses", &timestamp.naive_utc(), &IrcLine::Garbage),
+        None
+    );
+}
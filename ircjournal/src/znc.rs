@@ -0,0 +1,295 @@
+use lazy_static::lazy_static;
+use regex::{Match, Regex};
+use std::path::Path;
+
+use crate::{
+    model::{Datetime, ServerChannel},
+    IrcLine, LineParser, Logger, ParseResult,
+};
+
+lazy_static! {
+    static ref FNAME: Regex = Regex::new(r"^([0-9]{4}-[0-9]{2}-[0-9]{2})\.log$").unwrap();
+    static ref LINE: Regex = Regex::new(r"^\[([0-9]{2}:[0-9]{2}:[0-9]{2})\] (.*)$").unwrap();
+    static ref LOG_NICK_CHANGED: Regex =
+        Regex::new(r#"^\*\*\* (\S+) is now known as (\S+)$"#).unwrap();
+    static ref LOG_TOPIC_CHANGED: Regex =
+        Regex::new(r#"^\*\*\* (\S+) changes topic to '(.*)'$"#).unwrap();
+    static ref LOG_JOINED: Regex = Regex::new(r#"^\*\*\* Joins: (\S+) \(.*?\)$"#).unwrap();
+    static ref LOG_LEFT: Regex = Regex::new(r#"^\*\*\* Parts: (\S+) \(.*?\) \((.*)\)$"#).unwrap();
+    static ref LOG_QUIT: Regex = Regex::new(r#"^\*\*\* Quits: (\S+) \(.*?\) \((.*)\)$"#).unwrap();
+    static ref LOG_KICKED: Regex =
+        Regex::new(r#"^\*\*\* (\S+) was kicked by (\S+) \((.*)\)$"#).unwrap();
+    static ref LOG_MODE: Regex = Regex::new(r#"^\*\*\* (\S+) sets mode: (.*)$"#).unwrap();
+    static ref LOG_NOTICE: Regex = Regex::new(r#"^-(\S+?)- (.*)$"#).unwrap();
+    static ref LOG_ME: Regex = Regex::new(r#"^\* (\S+)(?: (.*))?$"#).unwrap();
+    static ref LOG_MESSAGE: Regex = Regex::new(r#"^<([^\s>]+)> ?(.*)$"#).unwrap();
+}
+
+fn parse_date(path: &Path) -> Option<chrono::NaiveDate> {
+    let captures = FNAME.captures(path.file_name()?.to_str()?)?;
+    chrono::NaiveDate::parse_from_str(captures.get(1)?.as_str(), "%Y-%m-%d").ok()
+}
+
+/// ZNC's `log` module format, one file per day in `<network>/<channel>/<YYYY-MM-DD>.log`.
+/// Lines only carry the time of day, the date comes from the file name.
+#[derive(Default, Clone)]
+pub struct Znc {
+    date: Option<chrono::NaiveDate>,
+}
+
+impl Logger for Znc {
+    const ROLLS_OVER: bool = true;
+
+    fn parse_path(path: &Path) -> Option<ServerChannel> {
+        parse_date(path)?;
+        let channel = path.parent()?;
+        let server = channel.parent()?;
+        Some(ServerChannel {
+            server: server.file_name()?.to_str()?.to_string(),
+            channel: channel.file_name()?.to_str()?.to_string(),
+        })
+    }
+
+    fn for_path(path: &Path) -> Self {
+        Self {
+            date: parse_date(path),
+        }
+    }
+
+    fn continues(path: &Path, next: &Path) -> bool {
+        match (parse_date(path), parse_date(next)) {
+            (Some(date), Some(next_date)) => path.parent() == next.parent() && date < next_date,
+            _ => false,
+        }
+    }
+}
+
+impl LineParser for Znc {
+    fn parse_line(&mut self, line: &str) -> ParseResult {
+        let mstr = |om: Match| om.as_str().to_string();
+        let cap = match LINE.captures(line) {
+            Some(cap) => cap,
+            None => return ParseResult::Invalid,
+        };
+        let time = match chrono::NaiveTime::parse_from_str(cap.get(1).unwrap().as_str(), "%H:%M:%S")
+        {
+            Ok(t) => t,
+            Err(_) => return ParseResult::Invalid,
+        };
+        let naive = match self.date {
+            Some(date) => date.and_time(time),
+            None => return ParseResult::Invalid,
+        };
+        let timestamp = Datetime::from_naive_utc_and_offset(naive, chrono::Utc);
+        let s = cap.get(2).unwrap().as_str();
+        let parsed = (|| {
+            if LOG_JOINED.is_match(s) {
+                let x = LOG_JOINED.captures(s).unwrap();
+                Some(IrcLine::Joined {
+                    nick: mstr(x.get(1)?),
+                })
+            } else if LOG_LEFT.is_match(s) {
+                let x = LOG_LEFT.captures(s).unwrap();
+                Some(IrcLine::Left {
+                    nick: mstr(x.get(1)?),
+                    reason: mstr(x.get(2)?),
+                })
+            } else if LOG_QUIT.is_match(s) {
+                let x = LOG_QUIT.captures(s).unwrap();
+                Some(IrcLine::Quit {
+                    nick: mstr(x.get(1)?),
+                    reason: mstr(x.get(2)?),
+                })
+            } else if LOG_NICK_CHANGED.is_match(s) {
+                let x = LOG_NICK_CHANGED.captures(s).unwrap();
+                Some(IrcLine::NickChanged {
+                    old: mstr(x.get(1)?),
+                    new: mstr(x.get(2)?),
+                })
+            } else if LOG_TOPIC_CHANGED.is_match(s) {
+                let x = LOG_TOPIC_CHANGED.captures(s).unwrap();
+                Some(IrcLine::TopicChanged {
+                    nick: mstr(x.get(1)?),
+                    // ZNC does not log the previous topic.
+                    old: "".to_string(),
+                    new: mstr(x.get(2)?),
+                })
+            } else if LOG_KICKED.is_match(s) {
+                let x = LOG_KICKED.captures(s).unwrap();
+                Some(IrcLine::Kicked {
+                    oper_nick: mstr(x.get(2)?),
+                    nick: mstr(x.get(1)?),
+                    reason: mstr(x.get(3)?),
+                })
+            } else if LOG_MODE.is_match(s) {
+                let x = LOG_MODE.captures(s).unwrap();
+                Some(IrcLine::ModeChanged {
+                    nick: mstr(x.get(1)?),
+                    mode: mstr(x.get(2)?),
+                })
+            } else if LOG_NOTICE.is_match(s) {
+                let x = LOG_NOTICE.captures(s).unwrap();
+                Some(IrcLine::Notice {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else if LOG_ME.is_match(s) {
+                let x = LOG_ME.captures(s).unwrap();
+                Some(IrcLine::Me {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else if LOG_MESSAGE.is_match(s) {
+                let x = LOG_MESSAGE.captures(s).unwrap();
+                Some(IrcLine::Message {
+                    nick: mstr(x.get(1)?),
+                    line: mstr(x.get(2)?),
+                })
+            } else {
+                None
+            }
+        })();
+        match parsed {
+            Some(line) => ParseResult::Ok((timestamp, line)),
+            None => ParseResult::Noise,
+        }
+    }
+}
+
+#[test]
+fn test_parse_znc() {
+    use crate::test::ts;
+    assert_eq!(Znc::parse_path(Path::new("garbage")), None);
+    assert_eq!(Znc::parse_path(Path::new("libera/#bar/garbage.log")), None);
+    let path = Path::new("moddata/log/libera/##dieses/2019-12-14.log");
+    assert_eq!(
+        Znc::parse_path(path),
+        Some(ServerChannel {
+            server: "libera".to_string(),
+            channel: "##dieses".to_string(),
+        })
+    );
+    assert!(Znc::continues(
+        path,
+        Path::new("moddata/log/libera/##dieses/2019-12-15.log")
+    ));
+    assert!(!Znc::continues(
+        path,
+        Path::new("moddata/log/libera/##dieses/2019-12-13.log")
+    ));
+    assert!(!Znc::continues(
+        path,
+        Path::new("moddata/log/libera/#other/2019-12-15.log")
+    ));
+
+    // No date known.
+    assert_eq!(
+        Znc::default().parse_line("[23:12:14] <zopieux> test"),
+        ParseResult::Invalid
+    );
+    let mut znc = Znc::for_path(path);
+    assert_eq!(
+        znc.parse_line("[23:11:17] *** Joins: zopieux (~zopieux@unaffiliated/zopieux)"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:11:17"),
+            IrcLine::Joined {
+                nick: "zopieux".to_string()
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:12:14] <zopieux> test"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:12:14"),
+            IrcLine::Message {
+                nick: "zopieux".to_string(),
+                line: "test".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:13:00] * haileda uploaded an image: (68KiB)"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:13:00"),
+            IrcLine::Me {
+                nick: "haileda".to_string(),
+                line: "uploaded an image: (68KiB)".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line(
+            "[23:14:00] *** Quits: zopiuex (zopieux@unaffiliated/zopieux) (Quit: WeeChat 2.2)"
+        ),
+        ParseResult::Ok((
+            ts("2019-12-14 23:14:00"),
+            IrcLine::Quit {
+                nick: "zopiuex".to_string(),
+                reason: "Quit: WeeChat 2.2".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:15:00] *** Parts: Tycale (~Tycale@tycale.be) (Cya)"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:15:00"),
+            IrcLine::Left {
+                nick: "Tycale".to_string(),
+                reason: "Cya".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:16:00] *** rom1504 was kicked by thizanne (no u)"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:16:00"),
+            IrcLine::Kicked {
+                oper_nick: "thizanne".to_string(),
+                nick: "rom1504".to_string(),
+                reason: "no u".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:17:00] *** JuanTitor is now known as ordiclic"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:17:00"),
+            IrcLine::NickChanged {
+                old: "JuanTitor".to_string(),
+                new: "ordiclic".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:18:00] *** ChanServ changes topic to 'Joyeux 'anniversaire' zopieux'"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:18:00"),
+            IrcLine::TopicChanged {
+                nick: "ChanServ".to_string(),
+                old: "".to_string(),
+                new: "Joyeux 'anniversaire' zopieux".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:19:00] *** ChanServ sets mode: +o zopieux"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:19:00"),
+            IrcLine::ModeChanged {
+                nick: "ChanServ".to_string(),
+                mode: "+o zopieux".to_string(),
+            }
+        ))
+    );
+    assert_eq!(
+        znc.parse_line("[23:20:00] -ChanServ- Welcome!"),
+        ParseResult::Ok((
+            ts("2019-12-14 23:20:00"),
+            IrcLine::Notice {
+                nick: "ChanServ".to_string(),
+                line: "Welcome!".to_string(),
+            }
+        ))
+    );
+    assert_eq!(znc.parse_line("garbage"), ParseResult::Invalid);
+}